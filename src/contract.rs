@@ -1,17 +1,16 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{Uint128, Coin};
+use cosmwasm_std::{Coin, Decimal, Empty, Uint128};
 use cosmwasm_std::{
-    from_binary, to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError,
-    StdResult,
+    from_json, to_json_binary, Addr, Api, Binary, Deps, DepsMut, Env, MessageInfo, Order, Response, StdError,
+    StdResult, Storage, Timestamp,
 };
-use cw2::set_contract_version;
+use cw2::{get_contract_version, set_contract_version};
+use semver::Version;
+use cw20::Cw20ReceiveMsg;
 
 use crate::error::ContractError;
-use crate::{
-    msg::*, query::*, state::*, utils::*, 
-    MAX_VEST, FAKE_PRICE, MAX_PURCHASE_AMOUNT, BLOCKS_IN_WEEK
-};
+use crate::{msg::*, query::*, state::*, utils::*};
 
 const CONTRACT_NAME: &str = "crates.io:cpbond";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -34,12 +33,24 @@ pub fn instantiate(
         None => info.sender,
     };
 
+    let (beingsold_asset, cost_asset) = validate_sale_assets(deps.api, msg.beingsold_asset, msg.cost_asset)?;
+    validate_price_guards(&msg.price_guards)?;
+    validate_limits(&msg.limits)?;
+    validate_discount_curve(&msg.discount_curve, msg.limits.max_vest)?;
+
     CONFIG.save(
         deps.storage,
         &Config {
-            admin: validated_admin,
-            beingsold_denom: msg.beingsold_denom,
-            cost_denom: msg.cost_denom,
+            admin: Some(validated_admin),
+            beingsold_asset,
+            cost_asset,
+            price_source: validate_price_source(deps.api, msg.price_source)?,
+            price_guards: msg.price_guards,
+            limits: msg.limits,
+            discount_curve: msg.discount_curve,
+            proceeds_recipients: validate_proceeds_recipients(deps.api, msg.proceeds_recipients)?,
+            purchase_fee: validate_purchase_fee(deps.api, msg.purchase_fee)?,
+            timelock_blocks: msg.timelock_blocks,
         },
     )?;
 
@@ -47,11 +58,336 @@ pub fn instantiate(
 
     PAUSED.save(deps.storage, &true)?;
 
+    PRICE_BREACHES.save(deps.storage, &0_u32)?;
+
     BALANCE.save(deps.storage, &Uint128::from(0_u128))?;
 
+    LIABILITY.save(deps.storage, &Uint128::zero())?;
+
+    QUEUED_CHANGE_COUNT.save(deps.storage, &0_u64)?;
+
+    STATS.save(deps.storage, &Stats::default())?;
+
     Ok(Response::new().add_attribute("Called", "Instantiate"))
 }
 
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Migrate
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(
+    mut deps: DepsMut,
+    env: Env,
+    msg: MigrateMsg,
+) -> Result<Response, ContractError> {
+
+    let stored = get_contract_version(deps.storage)?;
+
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::WrongContract {
+            expected: CONTRACT_NAME.to_string(),
+            got: stored.contract,
+        });
+    }
+
+    let stored_version: Version = stored.version.parse()
+        .map_err(|_| ContractError::InvalidVersion { version: stored.version.clone() })?;
+    let new_version: Version = CONTRACT_VERSION.parse()
+        .map_err(|_| ContractError::InvalidVersion { version: CONTRACT_VERSION.to_string() })?;
+
+    if stored_version > new_version {
+        return Err(ContractError::CannotDowngrade {
+            from: stored_version.to_string(),
+            to: new_version.to_string(),
+        });
+    }
+
+    // State migrations, oldest first so a contract can skip several versions at once
+    if stored_version < Version::new(0, 2, 0) {
+        migrate_config_v0_2(deps.storage, deps.api, msg)?;
+        migrate_purchases_v0_2(deps.storage)?;
+        migrate_stats_v0_2(deps.storage)?;
+        migrate_balance_v0_2(deps.branch(), &env)?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION))
+}
+
+// 0.1.0 only stored the admin and two native denoms, the rest were constants in lib.rs
+// Limits and curve keep those constants, the price settings had no equivalent and come from MigrateMsg
+fn migrate_config_v0_2(
+    storage: &mut dyn Storage,
+    api: &dyn Api,
+    msg: MigrateMsg
+) -> Result<(), ContractError> {
+
+    let legacy: LegacyConfig = LEGACY_CONFIG.load(storage)?;
+
+    // 0.1.0 priced every purchase at a hardcoded FAKE_PRICE, there is nothing to carry over
+    let Some(price_source) = msg.price_source else {
+        return Err(ContractError::MissingMigrateField { field: "price_source".to_string(), version: "0.1.0".to_string() });
+    };
+
+    let Some(price_guards) = msg.price_guards else {
+        return Err(ContractError::MissingMigrateField { field: "price_guards".to_string(), version: "0.1.0".to_string() });
+    };
+
+    validate_price_guards(&price_guards)?;
+
+    let (beingsold_asset, cost_asset) = validate_sale_assets(
+        api,
+        AssetInfo::Native { denom: legacy.beingsold_denom },
+        AssetInfo::Native { denom: legacy.cost_denom },
+    )?;
+
+    CONFIG.save(
+        storage,
+        &Config {
+            admin: Some(legacy.admin),
+            beingsold_asset,
+            cost_asset,
+            price_source: validate_price_source(api, price_source)?,
+            price_guards,
+            // MAX_PURCHASE_AMOUNT, MAX_VEST, BLOCKS_IN_WEEK and the 5 position cap
+            limits: SaleLimits {
+                min_purchase_amount: Uint128::zero(),
+                max_purchase_amount: Uint128::from(500_000_000_u128),
+                min_vest: 0,
+                max_vest: 52,
+                blocks_in_week: 100_000,
+                max_positions: 5,
+            },
+            // 1% per week
+            discount_curve: DiscountCurve::Linear { slope: Decimal::percent(1) },
+            proceeds_recipients: vec![],
+            purchase_fee: None,
+            timelock_blocks: msg.timelock_blocks.unwrap_or_default(),
+        },
+    )?;
+
+    PRICE_BREACHES.save(storage, &0_u32)?;
+
+    QUEUED_CHANGE_COUNT.save(storage, &0_u64)?;
+
+    Ok(())
+}
+
+// 0.2.0 added Purchase.vest_start, old positions get the start implied by the current blocks_in_week
+// 0.1.0 took sold amounts out of BALANCE without tracking them, so LIABILITY is rebuilt from the open positions
+fn migrate_purchases_v0_2(
+    storage: &mut dyn Storage
+) -> Result<(), ContractError> {
+
+    let config: Config = CONFIG.load(storage)?;
+
+    let legacy: Vec<((Addr, u64), LegacyPurchase)> = LEGACY_PURCHASES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut liability = Uint128::zero();
+
+    for (key, old) in legacy {
+        let unclaimed = old.amount_purchased.checked_sub(old.already_claimed).map_err(StdError::from)?;
+        liability = liability.checked_add(unclaimed).map_err(StdError::from)?;
+
+        let vest_blocks = u64::from(old.vest_period).saturating_mul(config.limits.blocks_in_week);
+
+        PURCHASES.save(
+            storage,
+            key,
+            &Purchase {
+                vest_period: old.vest_period,
+                vest_expiration: old.vest_expiration,
+                vest_start: old.vest_expiration.saturating_sub(vest_blocks),
+                amount_purchased: old.amount_purchased,
+                already_claimed: old.already_claimed,
+                last_claim: old.last_claim,
+            },
+        )?;
+    }
+
+    LIABILITY.save(storage, &liability)?;
+
+    Ok(())
+}
+
+// STATS is seeded from the open positions
+// Closed positions, past deposits and proceeds weren't recorded in 0.1.0, so they start from zero
+fn migrate_stats_v0_2(
+    storage: &mut dyn Storage
+) -> Result<(), ContractError> {
+
+    let purchases: Vec<((Addr, u64), Purchase)> = PURCHASES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut stats = Stats::default();
+    let mut last_wallet: Option<Addr> = None;
+
+    // PURCHASES is ordered by wallet, so each buyer is counted the first time it shows up
+    for ((wallet, _), purchase) in purchases {
+        if last_wallet.as_ref() != Some(&wallet) {
+            BUYERS.save(storage, &wallet, &Empty {})?;
+            stats.unique_buyers += 1;
+            last_wallet = Some(wallet);
+        }
+
+        stats.active_positions += 1;
+        stats.total_sold = stats.total_sold.checked_add(purchase.amount_purchased).map_err(StdError::from)?;
+        stats.total_claimed = stats.total_claimed.checked_add(purchase.already_claimed).map_err(StdError::from)?;
+    }
+
+    STATS.save(storage, &stats)?;
+
+    Ok(())
+}
+
+// 0.1.0 credited AddSaleFunds in any accepted denom to BALANCE, so it can be above what the contract holds
+// BALANCE becomes whatever is held beyond LIABILITY, an insolvent store starts at 0 and shows the deficit
+fn migrate_balance_v0_2(
+    deps: DepsMut,
+    env: &Env
+) -> Result<(), ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    let holdings = query_asset_balance(deps.as_ref(), &config.beingsold_asset, &env.contract.address)?;
+    let liability = LIABILITY.load(deps.storage)?;
+
+    BALANCE.save(deps.storage, &holdings.saturating_sub(liability))?;
+
+    Ok(())
+}
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Sudo
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(
+    deps: DepsMut,
+    _env: Env,
+    msg: SudoMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::ForcePause {} => {
+            PAUSED.save(deps.storage, &true)?;
+            Ok(Response::new().add_attribute("Call", "Sudo force pause"))
+        },
+        SudoMsg::ReplaceAdmin {new_admin} => sudo_replace_admin(deps, new_admin),
+        SudoMsg::OverrideConfig {
+            price_source,
+            price_guards,
+            limits,
+            discount_curve,
+            proceeds_recipients,
+            purchase_fee,
+            timelock_blocks,
+        } => {
+            let mut config: Config = CONFIG.load(deps.storage)?;
+
+            if let Some(price_source) = price_source {
+                config.price_source = validate_price_source(deps.api, price_source)?;
+                reset_price_history(deps.storage)?;
+                TWAP_SNAPSHOTS.clear(deps.storage);
+            }
+
+            if let Some(price_guards) = price_guards {
+                validate_price_guards(&price_guards)?;
+                config.price_guards = price_guards;
+            }
+
+            if limits.is_some() || discount_curve.is_some() {
+                apply_limits_and_curve(&mut config, limits, discount_curve)?;
+            }
+
+            if let Some(proceeds_recipients) = proceeds_recipients {
+                config.proceeds_recipients = validate_proceeds_recipients(deps.api, proceeds_recipients)?;
+            }
+
+            if let Some(purchase_fee) = purchase_fee {
+                config.purchase_fee = validate_purchase_fee(deps.api, purchase_fee)?;
+            }
+
+            if let Some(timelock_blocks) = timelock_blocks {
+                config.timelock_blocks = timelock_blocks;
+            }
+
+            CONFIG.save(deps.storage, &config)?;
+
+            Ok(Response::new().add_attribute("Call", "Sudo override config"))
+        },
+        SudoMsg::EmergencyWithdraw {asset, amount, recipient} => {
+            sudo_emergency_withdraw(deps, asset, amount, recipient)
+        },
+    }
+}
+
+fn sudo_replace_admin(
+    deps: DepsMut,
+    new_admin: String
+) -> Result<Response, ContractError> {
+
+    let new_admin = deps.api.addr_validate(&new_admin)?;
+
+    CONFIG.update(
+        deps.storage,
+        |mut config| -> StdResult<Config> {
+            config.admin = Some(new_admin.clone());
+            Ok(config)
+        }
+    )?;
+
+    PENDING_ADMIN.remove(deps.storage);
+
+    Ok(Response::new()
+        .add_attribute("Call", "Sudo replace admin")
+        .add_attribute("admin", new_admin))
+}
+
+// Sale asset comes out of BALANCE first, anything past it is taken from tokens owed to positions
+fn sudo_emergency_withdraw(
+    deps: DepsMut,
+    asset: AssetInfo,
+    amount: Uint128,
+    recipient: String
+) -> Result<Response, ContractError> {
+
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount);
+    }
+
+    let config: Config = CONFIG.load(deps.storage)?;
+    let asset = validate_asset_info(deps.api, asset)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    if asset == config.beingsold_asset {
+        BALANCE.update(
+            deps.storage,
+            |o| -> StdResult<Uint128> {
+                Ok(o.saturating_sub(amount))
+            }
+        )?;
+    }
+
+    PAUSED.save(deps.storage, &true)?;
+
+    let send_msg = asset_transfer_msg(&asset, &recipient, amount)?;
+
+    Ok(Response::new()
+        .add_message(send_msg)
+        .add_attribute("Call", "Sudo emergency withdraw")
+        .add_attribute("asset", asset.to_string())
+        .add_attribute("amount", amount)
+        .add_attribute("recipient", recipient))
+}
+
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // Execute
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -66,45 +402,69 @@ pub fn execute(
     match msg {
 
         //~~~~~~~~~~~~~~
-        // Admin Only
+        // Admin / Roles
         //~~~~~~~~~~~~~~
         ExecuteMsg::PausePurchasing {} => pause_purchasing(deps, env, &info.sender),
         ExecuteMsg::ResumePurchasing {} => resume_purchasing(deps, env, &info.sender),
+        ExecuteMsg::ProposeAdmin {new_admin, expiry} => propose_admin(deps, env, &info.sender, new_admin, expiry),
+        ExecuteMsg::CancelAdminProposal {} => cancel_admin_proposal(deps, &info.sender),
+        ExecuteMsg::RenounceAdmin {} => renounce_admin(deps, &info.sender),
+        ExecuteMsg::GrantRole {address, role} => grant_role(deps, &info.sender, address, role),
+        ExecuteMsg::RevokeRole {address, role} => revoke_role(deps, &info.sender, address, role),
         ExecuteMsg::AddSaleFunds {} => add_sale_funds(deps, env, &info.sender, &info.funds),
+        ExecuteMsg::SyncInventory {} => sync_inventory(deps, env, &info.sender),
+        ExecuteMsg::Receive(wrapper) => receive_cw20(deps, env, info, wrapper),
+        ExecuteMsg::AddPriceSource {contract} => add_price_source(deps, &info.sender, contract),
+        ExecuteMsg::RemovePriceSource {contract} => remove_price_source(deps, &info.sender, contract),
+        ExecuteMsg::UpdateConfig {price_guards, limits, discount_curve, proceeds_recipients} => {
+            update_config(deps, &info.sender, price_guards, limits, discount_curve, proceeds_recipients)
+        },
+        ExecuteMsg::UpdatePurchaseFee {purchase_fee} => update_purchase_fee(deps, &info.sender, purchase_fee),
+        ExecuteMsg::QueueConfigChange {change} => queue_config_change(deps, env, &info.sender, change),
+        ExecuteMsg::ExecuteQueuedChange {id} => execute_queued_change(deps, env, &info.sender, id),
+        ExecuteMsg::CancelQueuedChange {id} => cancel_queued_change(deps, &info.sender, id),
+        ExecuteMsg::ResetPriceAnchor {} => reset_price_anchor(deps, env, &info.sender),
+        ExecuteMsg::WithdrawInventory {amount} => withdraw_inventory(deps, &info.sender, amount),
+        ExecuteMsg::WithdrawProceeds {asset, amount, recipient} => {
+            withdraw_proceeds(deps, env, &info.sender, asset, amount, recipient)
+        },
 
         //~~~~~~~~~~~~~~
         // User
         //~~~~~~~~~~~~~~
         ExecuteMsg::Purchase {vesting_period} => {
-            if PAUSED.load(deps.storage)? {
-                Err(ContractError::GenericError("Purchasing is paused".to_string()))
-            } else {
-                execute_purchase(deps, env, info.sender, &info.funds, vesting_period)
-            }
+            // CHECK: denom = cost_asset denom | not empty | only 1 coin
+            let config: Config = CONFIG.load(deps.storage)?;
+            let cost_amount = purchase_funds_check(&config.cost_asset, &info.funds)?;
+            execute_purchase(deps, env, info.sender, cost_amount, vesting_period)
         },
+        ExecuteMsg::AcceptAdmin {} => accept_admin(deps, env, info.sender),
         ExecuteMsg::ClaimAvailable {uuid} => claim_available(deps, env, info.sender, uuid),
+        ExecuteMsg::ClaimAll {limit} => claim_all(deps, env, info.sender, limit),
+
+        //~~~~~~~~~~~~~~
+        // Permissionless
+        //~~~~~~~~~~~~~~
+        ExecuteMsg::CheckPrice {} => check_price(deps, env),
+        ExecuteMsg::RecordTwapSnapshot {} => {
+            let config: Config = CONFIG.load(deps.storage)?;
+            record_twap_snapshot(deps, &config)?;
+            Ok(Response::new().add_attribute("Call", "Record TWAP snapshot"))
+        },
     }
 }
 
 pub fn pause_purchasing(
     deps: DepsMut, 
-    env: Env, 
+    _env: Env, 
     sender: &Addr
 ) -> Result<Response, ContractError> {
 
     let config: Config = CONFIG.load(deps.storage)?;
 
-    if sender != &config.admin {
-        return Err(ContractError::Unauthorized);
-    } else {
-        PAUSED.update(
-            deps.storage,
-            |x| -> StdResult<bool> {
-                Ok(true)
-            }
-        )?;
-    };
+    role_check(deps.storage, &config, sender, Role::Pauser)?;
 
+    PAUSED.save(deps.storage, &true)?;
 
     Ok(Response::default())
 
@@ -112,176 +472,988 @@ pub fn pause_purchasing(
 
 pub fn resume_purchasing(
     deps: DepsMut, 
-    env: Env, 
+    _env: Env, 
     sender: &Addr
 ) -> Result<Response, ContractError> {
 
     let config: Config = CONFIG.load(deps.storage)?;
 
-    if sender != &config.admin {
-        return Err(ContractError::Unauthorized);
-    } else {
-        PAUSED.update(
-            deps.storage,
-            |x| -> StdResult<bool> {
-                Ok(false)
-            }
-        )?;
-    };
+    role_check(deps.storage, &config, sender, Role::Pauser)?;
 
+    PAUSED.save(deps.storage, &false)?;
+
+    // LAST_PRICE is kept, if the market really moved the admin re-anchors with ResetPriceAnchor
+    PRICE_BREACHES.save(deps.storage, &0_u32)?;
 
     Ok(Response::default())
 
 }
 
-pub fn add_sale_funds(
-    deps: DepsMut, 
-    env: Env, 
+// First step of an admin transfer, replaces any earlier proposal
+pub fn propose_admin(
+    deps: DepsMut,
+    env: Env,
     sender: &Addr,
-    funds: &[Coin]
+    new_admin: String,
+    expiry: Option<Timestamp>
 ) -> Result<Response, ContractError> {
 
     let config: Config = CONFIG.load(deps.storage)?;
 
-    // if sender != &config.admin {
-    //     return Err(ContractError::Unauthorized);
-    // };
+    if config.admin.as_ref() != Some(sender) {
+        return Err(ContractError::Unauthorized);
+    }
 
-    // assert not empty
-    if funds.is_empty() {
-        return Err(ContractError::GenericError("Purchase funds empty".to_string()));
-    };
+    not_timelocked_check(&config)?;
 
-    // assert only 1 coin
-    if funds.len() != 1 {
-        return Err(ContractError::GenericError("More than 1 coin sent".to_string()));
-    };
+    let new_admin = deps.api.addr_validate(&new_admin)?;
+
+    if let Some(expiry) = expiry {
+        if expiry <= env.block.time {
+            return Err(ContractError::ExpiryInPast { expiry });
+        }
+    }
+
+    PENDING_ADMIN.save(deps.storage, &PendingAdmin { new_admin: new_admin.clone(), expiry })?;
 
-    // assert denom = cost_denom
-    if funds[0].denom != config.cost_denom {
-        return Err(ContractError::GenericError("Funds used for purchase wrong denom".to_string()));
+    Ok(Response::new()
+        .add_attribute("Call", "Propose admin")
+        .add_attribute("new_admin", new_admin))
+}
+
+// Second step, only the proposed address can accept
+pub fn accept_admin(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr
+) -> Result<Response, ContractError> {
+
+    let Some(pending) = PENDING_ADMIN.may_load(deps.storage)? else {
+        return Err(ContractError::NoPendingAdmin);
     };
 
+    if sender != pending.new_admin {
+        return Err(ContractError::Unauthorized);
+    }
+
+    if let Some(expiry) = pending.expiry {
+        if env.block.time >= expiry {
+            return Err(ContractError::AdminProposalExpired { expiry });
+        }
+    }
 
-    // Checks pass, update balance
-    BALANCE.update(
-        deps.storage, 
-        |o| -> StdResult<Uint128> {
-            o.checked_add(funds[0].amount).map_err(|e| e.into())
+    CONFIG.update(
+        deps.storage,
+        |mut config| -> StdResult<Config> {
+            config.admin = Some(sender.clone());
+            Ok(config)
         }
     )?;
 
-    Ok(Response::default())
+    PENDING_ADMIN.remove(deps.storage);
+
+    Ok(Response::new()
+        .add_attribute("Call", "Accept admin")
+        .add_attribute("admin", sender))
 }
 
-pub fn execute_purchase(
+pub fn cancel_admin_proposal(
     deps: DepsMut,
-    env: Env,
-    user_wallet: Addr,
-    user_funds: &[Coin],
-    vesting_period: u128,
+    sender: &Addr
 ) -> Result<Response, ContractError> {
 
-    if vesting_period > MAX_VEST {
-        return Err(ContractError::GenericError("Max vesting period is 52 weeks".to_string()));
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.admin.as_ref() != Some(sender) {
+        return Err(ContractError::Unauthorized);
     }
 
-    // Check that user doesn't already have 5 purchases
-    amount_of_purchases_check(user_wallet.clone(), deps.as_ref())?;
+    if PENDING_ADMIN.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoPendingAdmin);
+    }
 
-    // CHECK: denom = cost_denom | not empty | only 1 coin | not greater than max purchase amount
-    let config: Config = CONFIG.load(deps.storage)?;
-    purchase_funds_check(config.cost_denom, user_funds)?;
+    PENDING_ADMIN.remove(deps.storage);
 
-    // fully vested block height = vesting_period * blocks_in_week + current_block_height
-    let vesting_blocks = vesting_period
-        .checked_mul(BLOCKS_IN_WEEK)
-        .ok_or_else(|| ContractError::GenericError("vesting_blocks overflow".to_string()))?;
+    Ok(Response::new().add_attribute("Call", "Cancel admin proposal"))
+}
 
-    let fully_vested_blockheight = vesting_blocks
-        .checked_add(env.block.height.into())
-        .ok_or_else(|| ContractError::GenericError("fully_vested_blockheight overflow".to_string()))?;
+// Permanently leaves the contract without an admin, pending proposals are dropped too
+pub fn renounce_admin(
+    deps: DepsMut,
+    sender: &Addr
+) -> Result<Response, ContractError> {
 
-    //~~~~~
-    //~~~~~
-    // This is where a query to price oracle would happen, FAKE_PRICE used for time being
-    //~~~~~
-    //~~~~~
+    let mut config: Config = CONFIG.load(deps.storage)?;
 
-    // Calculate amount being purchased using vesting_period for discount, price, and amount of funds sent in
-    let juno_to_user = calc_juno_amt(vesting_period, FAKE_PRICE, user_funds[0].amount)?;
+    if config.admin.as_ref() != Some(sender) {
+        return Err(ContractError::Unauthorized);
+    }
 
-    // Make sure contract has enough JUNO to complete purchase
-    let contract_balance = BALANCE.load(deps.storage)?;
+    not_timelocked_check(&config)?;
 
-    if juno_to_user >= contract_balance {
-        return Err(
-            ContractError::GenericError(
-                format!("Not enough JUNO to complete sale | JUNO: {} | juno_to_user: {}", contract_balance, juno_to_user)
-            ));
-    }
+    config.admin = None;
+    CONFIG.save(deps.storage, &config)?;
 
-    // Create Purchase Item in state with user_address, UUID, amount, and purchase object
-    let uuid = PURCHASE_COUNT.load(deps.storage)?;
+    PENDING_ADMIN.remove(deps.storage);
 
-    let purchase: Purchase = Purchase {
-        vest_period: vesting_period.try_into().map_err(|_| ContractError::ToDo)?,
-        vest_expiration: fully_vested_blockheight.try_into().map_err(|_| ContractError::ToDo)?,
-        amount_purchased: juno_to_user,
-        already_claimed: Uint128::from(0_u128),
-        last_claim: env.block.height,
-        //closed: false,
-    };
+    Ok(Response::new().add_attribute("Call", "Renounce admin"))
+}
 
-    // Save new purchase
-    PURCHASES
-        .save(deps.storage, (user_wallet, uuid), &purchase)
-        .map_err(|_| ContractError::GenericError("Save Purchase Error".to_string()))?;
+pub fn grant_role(
+    deps: DepsMut,
+    sender: &Addr,
+    address: String,
+    role: Role
+) -> Result<Response, ContractError> {
 
-    // Update contract available balance
-    BALANCE
-        .update(deps.storage, |o| -> StdResult<Uint128> {
-            o.checked_sub(juno_to_user).map_err(|e| e.into())
-        })?;
+    let config: Config = CONFIG.load(deps.storage)?;
 
-    // Update purchase count
-    PURCHASE_COUNT
-        .update(deps.storage, |old| -> Result<u64, ContractError> { // initialized on init
-            if old >= u64::MAX - 1_u64 {
-                Ok(1_u64)
-            } else {
-                Ok(old + 1_u64)
-            }
-        })
-        .map_err(|_| ContractError::GenericError("Update purchase count error".to_string()))?;
+    let address = deps.api.addr_validate(&address)?;
+
+    grant_role_check(deps.storage, &config, sender, &address, &role)?;
+
+    let mut roles = ROLES.may_load(deps.storage, &address)?.unwrap_or_default();
+
+    if roles.contains(&role) {
+        return Err(ContractError::RoleAlreadyGranted {
+            address: address.to_string(),
+            role: role.to_string(),
+        });
+    }
 
-    // Decide whether funds from user stay in this contract or go elsewhere like community pool
-    Ok(Response::new().add_attribute("Call", "Purchase"))
+    roles.push(role.clone());
+    ROLES.save(deps.storage, &address, &roles)?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Grant role")
+        .add_attribute("address", address)
+        .add_attribute("role", role.to_string()))
 }
 
-pub fn claim_available(
+pub fn revoke_role(
     deps: DepsMut,
-    env: Env,
-    user_wallet: Addr,
-    uuid: u64
+    sender: &Addr,
+    address: String,
+    role: Role
 ) -> Result<Response, ContractError> {
 
-    let purchase: Purchase = PURCHASES.load(deps.storage, (user_wallet.clone(), uuid))?;
+    let config: Config = CONFIG.load(deps.storage)?;
 
-    // First - Do checks
-    claim_vestable_checks(&purchase, env.block.height)?;
+    let address = deps.api.addr_validate(&address)?;
 
-    // Second - Calculate amount to be vested & sent
-    let (amount_vestable, close_purchase) = calc_amt_vested(&purchase, env.block.height)?;
+    grant_role_check(deps.storage, &config, sender, &address, &role)?;
 
-    if close_purchase {
-        PURCHASES.remove(
-            deps.storage,
-            (user_wallet.clone(), uuid)
-        );
+    let mut roles = ROLES.may_load(deps.storage, &address)?.unwrap_or_default();
+
+    let Some(index) = roles.iter().position(|r| r == &role) else {
+        return Err(ContractError::RoleNotGranted {
+            address: address.to_string(),
+            role: role.to_string(),
+        });
+    };
+
+    roles.remove(index);
+
+    if roles.is_empty() {
+        ROLES.remove(deps.storage, &address);
     } else {
-        PURCHASES.update(
-            deps.storage,
+        ROLES.save(deps.storage, &address, &roles)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("Call", "Revoke role")
+        .add_attribute("address", address)
+        .add_attribute("role", role.to_string()))
+}
+
+pub fn add_sale_funds(
+    deps: DepsMut, 
+    _env: Env, 
+    _sender: &Addr,
+    funds: &[Coin]
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    // assert not empty
+    if funds.is_empty() {
+        return Err(ContractError::NoFunds);
+    };
+
+    // assert only 1 coin
+    if funds.len() != 1 {
+        return Err(ContractError::MultipleCoins);
+    };
+
+    // assert denom = beingsold_asset denom, cw20 sale assets are added through Receive
+    let AssetInfo::Native { denom } = config.beingsold_asset else {
+        return Err(ContractError::UseCw20Send { asset: config.beingsold_asset.to_string() });
+    };
+
+    if funds[0].denom != denom {
+        return Err(ContractError::WrongDenom {
+            expected: denom,
+            got: funds[0].denom.clone(),
+        });
+    };
+
+    // Checks pass, update balance
+    credit_sale_funds(deps.storage, funds[0].amount)
+}
+
+pub fn receive_cw20(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    // info.sender is the cw20 contract, wrapper.sender is who sent the tokens
+    match from_json(&wrapper.msg)? {
+        ReceiveMsg::AddSaleFunds {} => {
+            if config.beingsold_asset != (AssetInfo::Cw20 { contract: info.sender.clone() }) {
+                return Err(ContractError::WrongDenom {
+                    expected: config.beingsold_asset.to_string(),
+                    got: info.sender.to_string(),
+                });
+            }
+
+            credit_sale_funds(deps.storage, wrapper.amount)
+        },
+        ReceiveMsg::Purchase { vesting_period } => {
+            if config.cost_asset != (AssetInfo::Cw20 { contract: info.sender.clone() }) {
+                return Err(ContractError::WrongDenom {
+                    expected: config.cost_asset.to_string(),
+                    got: info.sender.to_string(),
+                });
+            }
+
+            let buyer = deps.api.addr_validate(&wrapper.sender)?;
+            execute_purchase(deps, env, buyer, wrapper.amount, vesting_period)
+        },
+    }
+}
+
+fn credit_sale_funds(
+    storage: &mut dyn Storage,
+    amount: Uint128
+) -> Result<Response, ContractError> {
+
+    if amount.is_zero() {
+        return Err(ContractError::NoFunds);
+    }
+
+    BALANCE.update(
+        storage, 
+        |o| -> StdResult<Uint128> {
+            o.checked_add(amount).map_err(|e| e.into())
+        }
+    )?;
+
+    STATS.update(storage, |mut stats| -> StdResult<Stats> {
+        stats.total_deposited = stats.total_deposited.checked_add(amount)?;
+        Ok(stats)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Add sale funds")
+        .add_attribute("amount", amount))
+}
+
+// Sale tokens sent straight to the contract (BankMsg::Send / cw20 Transfer) aren't in BALANCE until synced
+pub fn sync_inventory(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.admin.as_ref() != Some(sender) {
+        return Err(ContractError::Unauthorized);
+    }
+
+    let audit = audit_solvency(deps.as_ref(), &config, &env.contract.address)?;
+
+    if audit.surplus.is_zero() {
+        return Err(ContractError::NoSurplus);
+    }
+
+    BALANCE.update(
+        deps.storage,
+        |o| -> StdResult<Uint128> {
+            o.checked_add(audit.surplus).map_err(|e| e.into())
+        }
+    )?;
+
+    STATS.update(deps.storage, |mut stats| -> StdResult<Stats> {
+        stats.total_deposited = stats.total_deposited.checked_add(audit.surplus)?;
+        Ok(stats)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Sync inventory")
+        .add_attribute("amount", audit.surplus))
+}
+
+pub fn add_price_source(
+    deps: DepsMut,
+    sender: &Addr,
+    contract: String
+) -> Result<Response, ContractError> {
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    role_check(deps.storage, &config, sender, Role::ConfigManager)?;
+
+    not_timelocked_check(&config)?;
+
+    let contract = deps.api.addr_validate(&contract)?;
+
+    let PriceSource::Median { sources, min_responses } = &mut config.price_source else {
+        return Err(ContractError::PriceSourceNotMedian);
+    };
+
+    sources.push(contract.clone());
+    median_sources_check(sources, *min_responses)?;
+
+    CONFIG.save(deps.storage, &config)?;
+    reset_price_history(deps.storage)?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Add price source")
+        .add_attribute("contract", contract))
+}
+
+pub fn remove_price_source(
+    deps: DepsMut,
+    sender: &Addr,
+    contract: String
+) -> Result<Response, ContractError> {
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    role_check(deps.storage, &config, sender, Role::ConfigManager)?;
+
+    not_timelocked_check(&config)?;
+
+    let contract = deps.api.addr_validate(&contract)?;
+
+    let PriceSource::Median { sources, min_responses } = &mut config.price_source else {
+        return Err(ContractError::PriceSourceNotMedian);
+    };
+
+    let Some(index) = sources.iter().position(|s| s == contract) else {
+        return Err(ContractError::PriceSourceNotFound { contract: contract.to_string() });
+    };
+
+    sources.remove(index);
+    median_sources_check(sources, *min_responses)?;
+
+    CONFIG.save(deps.storage, &config)?;
+    reset_price_history(deps.storage)?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Remove price source")
+        .add_attribute("contract", contract))
+}
+
+pub fn update_config(
+    deps: DepsMut,
+    sender: &Addr,
+    price_guards: Option<PriceGuards>,
+    limits: Option<SaleLimits>,
+    discount_curve: Option<DiscountCurve>,
+    proceeds_recipients: Option<Vec<(String, Decimal)>>
+) -> Result<Response, ContractError> {
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    role_check(deps.storage, &config, sender, Role::ConfigManager)?;
+
+    not_timelocked_check(&config)?;
+
+    if let Some(price_guards) = price_guards {
+        validate_price_guards(&price_guards)?;
+        config.price_guards = price_guards;
+    }
+
+    if limits.is_some() || discount_curve.is_some() {
+        apply_limits_and_curve(&mut config, limits, discount_curve)?;
+    }
+
+    if let Some(proceeds_recipients) = proceeds_recipients {
+        config.proceeds_recipients = validate_proceeds_recipients(deps.api, proceeds_recipients)?;
+    }
+
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("Call", "Update config"))
+}
+
+// Validated now so buyers see exactly what will apply at activation_height
+pub fn queue_config_change(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    change: ConfigChange
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    config_change_role_check(deps.storage, &config, sender, &change)?;
+
+    let change = validate_config_change(deps.api, &config, change)?;
+
+    let activation_height = env.block.height
+        .checked_add(config.timelock_blocks)
+        .ok_or_else(|| ContractError::Overflow { context: "activation_height".to_string() })?;
+
+    let id = QUEUED_CHANGE_COUNT.update(
+        deps.storage,
+        |o| -> StdResult<u64> {
+            Ok(o + 1)
+        }
+    )?;
+
+    QUEUED_CHANGES.save(deps.storage, id, &QueuedChange { change, activation_height })?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Queue config change")
+        .add_attribute("id", id.to_string())
+        .add_attribute("activation_height", activation_height.to_string()))
+}
+
+pub fn execute_queued_change(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    id: u64
+) -> Result<Response, ContractError> {
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    let Some(queued) = QUEUED_CHANGES.may_load(deps.storage, id)? else {
+        return Err(ContractError::QueuedChangeNotFound { id });
+    };
+
+    config_change_role_check(deps.storage, &config, sender, &queued.change)?;
+
+    if env.block.height < queued.activation_height {
+        return Err(ContractError::QueuedChangeNotReady {
+            id,
+            activation_height: queued.activation_height,
+        });
+    }
+
+    match queued.change {
+        ConfigChange::PriceSource { price_source } => {
+            config.price_source = price_source;
+            reset_price_history(deps.storage)?;
+            // Snapshots belong to the old pair, a new pair starts its window from scratch
+            TWAP_SNAPSHOTS.clear(deps.storage);
+        },
+        // The other half may have changed since this was queued
+        ConfigChange::DiscountCurve { discount_curve } => apply_limits_and_curve(&mut config, None, Some(discount_curve))?,
+        ConfigChange::Limits { limits } => apply_limits_and_curve(&mut config, Some(limits), None)?,
+        ConfigChange::ProceedsRecipients { proceeds_recipients } => config.proceeds_recipients = proceeds_recipients,
+        ConfigChange::PurchaseFee { purchase_fee } => config.purchase_fee = purchase_fee,
+        ConfigChange::PriceGuards { price_guards } => config.price_guards = price_guards,
+        ConfigChange::Admin { new_admin } => {
+            PENDING_ADMIN.save(deps.storage, &PendingAdmin { new_admin, expiry: None })?;
+        },
+        ConfigChange::TimelockBlocks { timelock_blocks } => config.timelock_blocks = timelock_blocks,
+    }
+
+    CONFIG.save(deps.storage, &config)?;
+
+    QUEUED_CHANGES.remove(deps.storage, id);
+
+    Ok(Response::new()
+        .add_attribute("Call", "Execute queued change")
+        .add_attribute("id", id.to_string()))
+}
+
+pub fn cancel_queued_change(
+    deps: DepsMut,
+    sender: &Addr,
+    id: u64
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    let Some(queued) = QUEUED_CHANGES.may_load(deps.storage, id)? else {
+        return Err(ContractError::QueuedChangeNotFound { id });
+    };
+
+    config_change_role_check(deps.storage, &config, sender, &queued.change)?;
+
+    QUEUED_CHANGES.remove(deps.storage, id);
+
+    Ok(Response::new()
+        .add_attribute("Call", "Cancel queued change")
+        .add_attribute("id", id.to_string()))
+}
+
+pub fn update_purchase_fee(
+    deps: DepsMut,
+    sender: &Addr,
+    purchase_fee: Option<PurchaseFee>
+) -> Result<Response, ContractError> {
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    role_check(deps.storage, &config, sender, Role::ConfigManager)?;
+
+    not_timelocked_check(&config)?;
+
+    config.purchase_fee = validate_purchase_fee(deps.api, purchase_fee)?;
+
+    CONFIG.save(deps.storage, &config)?;
+
+    let bps = config.purchase_fee.map(|fee| fee.bps).unwrap_or_default();
+
+    Ok(Response::new()
+        .add_attribute("Call", "Update purchase fee")
+        .add_attribute("bps", bps.to_string()))
+}
+
+// Unsold sale tokens, only ever taken out of BALANCE
+pub fn withdraw_inventory(
+    deps: DepsMut,
+    sender: &Addr,
+    amount: Uint128
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    role_check(deps.storage, &config, sender, Role::Treasurer)?;
+
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount);
+    }
+
+    BALANCE.update(
+        deps.storage,
+        |o| -> Result<Uint128, ContractError> {
+            o.checked_sub(amount).map_err(|_| {
+                ContractError::InsufficientInventory { available: o, requested: amount }
+            })
+        }
+    )?;
+
+    let send_msg = asset_transfer_msg(&config.beingsold_asset, sender, amount)?;
+
+    Ok(Response::new()
+        .add_message(send_msg)
+        .add_attribute("Call", "Withdraw inventory")
+        .add_attribute("amount", amount))
+}
+
+// Any token held by the contract, minus BALANCE and LIABILITY when it is the sale asset
+pub fn withdraw_proceeds(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr,
+    asset: AssetInfo,
+    amount: Uint128,
+    recipient: String
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    role_check(deps.storage, &config, sender, Role::Treasurer)?;
+
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount);
+    }
+
+    let asset = validate_asset_info(deps.api, asset)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    let holdings = query_asset_balance(deps.as_ref(), &asset, &env.contract.address)?;
+
+    let reserved = if asset == config.beingsold_asset {
+        BALANCE.load(deps.storage)?.checked_add(LIABILITY.load(deps.storage)?).map_err(StdError::from)?
+    } else {
+        Uint128::zero()
+    };
+
+    let available = holdings.saturating_sub(reserved);
+
+    if amount > available {
+        return Err(ContractError::InsufficientProceeds { available, requested: amount });
+    }
+
+    let send_msg = asset_transfer_msg(&asset, &recipient, amount)?;
+
+    Ok(Response::new()
+        .add_message(send_msg)
+        .add_attribute("Call", "Withdraw proceeds")
+        .add_attribute("asset", asset.to_string())
+        .add_attribute("amount", amount)
+        .add_attribute("recipient", recipient))
+}
+
+pub fn execute_purchase(
+    mut deps: DepsMut,
+    env: Env,
+    user_wallet: Addr,
+    cost_amount: Uint128,
+    vesting_period: u128,
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    // Check that user doesn't already have max_positions purchases
+    amount_of_purchases_check(user_wallet.clone(), config.limits.max_positions, deps.as_ref())?;
+
+    // Vesting period, purchase amount and price guard checks, then the amounts owed
+    let quote = quote_purchase(deps.as_ref(), &env, &config, cost_amount, vesting_period)?;
+    inventory_check(deps.storage, quote.gross_amount)?;
+
+    let PurchaseQuote { cost_fee, net_cost, gross_amount: gross_juno, output_fee, amount_purchased: juno_to_user, .. } = quote;
+
+    // Price passed the guards, store it for the next deviation check
+    let bond_debt = current_bond_debt(deps.storage, &config.discount_curve, env.block.height)?;
+    accept_price(deps.storage, quote.price, env.block.time)?;
+    record_twap_snapshot(deps.branch(), &config)?;
+
+    // Create Purchase Item in state with user_address, UUID, amount, and purchase object
+    let uuid = PURCHASE_COUNT.load(deps.storage)?;
+
+    let purchase: Purchase = Purchase {
+        vest_period: vesting_period.try_into().map_err(|_| ContractError::Overflow { context: "vest_period".to_string() })?,
+        vest_expiration: quote.vest_expiration,
+        vest_start: env.block.height,
+        amount_purchased: juno_to_user,
+        already_claimed: Uint128::from(0_u128),
+        last_claim: env.block.height,
+        //closed: false,
+    };
+
+    // Save new purchase
+    PURCHASES
+        .save(deps.storage, (user_wallet.clone(), uuid), &purchase)?;
+
+    // Update contract available balance, tokens now owed to the purchase
+    BALANCE
+        .update(deps.storage, |o| -> StdResult<Uint128> {
+            o.checked_sub(gross_juno).map_err(|e| e.into())
+        })?;
+
+    LIABILITY
+        .update(deps.storage, |o| -> StdResult<Uint128> {
+            o.checked_add(juno_to_user).map_err(|e| e.into())
+        })?;
+
+    // Running totals for GetStats
+    let new_buyer = !BUYERS.has(deps.storage, &user_wallet);
+    if new_buyer {
+        BUYERS.save(deps.storage, &user_wallet, &Empty {})?;
+    }
+
+    STATS.update(deps.storage, |mut stats| -> StdResult<Stats> {
+        stats.total_sold = stats.total_sold.checked_add(juno_to_user)?;
+        stats.active_positions += 1;
+        if new_buyer {
+            stats.unique_buyers += 1;
+        }
+        Ok(stats)
+    })?;
+
+    PROCEEDS_RAISED.update(deps.storage, config.cost_asset.to_string(), |old| -> StdResult<Uint128> {
+        Ok(old.unwrap_or_default().checked_add(cost_amount)?)
+    })?;
+
+    // Add purchase to bond debt, only tracked for DebtRatio curve
+    if let DiscountCurve::DebtRatio { .. } = config.discount_curve {
+        BOND_DEBT.save(
+            deps.storage,
+            &BondDebt {
+                debt: bond_debt.checked_add(gross_juno).map_err(StdError::from)?,
+                last_decay: env.block.height,
+            },
+        )?;
+    }
+
+    // Update purchase count
+    PURCHASE_COUNT
+        .update(deps.storage, |old| -> Result<u64, ContractError> { // initialized on init
+            if old >= u64::MAX - 1_u64 {
+                Ok(1_u64)
+            } else {
+                Ok(old + 1_u64)
+            }
+        })?;
+
+    let mut res = Response::new()
+        .add_attribute("Call", "Purchase")
+        .add_attribute("uuid", uuid.to_string())
+        .add_attribute("cost_amount", cost_amount)
+        .add_attribute("amount_purchased", juno_to_user);
+
+    // Fee goes to the collector, gross / fee / net are in the asset the fee was taken from
+    if let Some(fee) = &config.purchase_fee {
+        let (fee_asset, gross, fee_amount, net) = match fee.deduct_from {
+            FeeSource::Cost => (&config.cost_asset, cost_amount, cost_fee, net_cost),
+            FeeSource::Output => (&config.beingsold_asset, gross_juno, output_fee, juno_to_user),
+        };
+
+        if !fee_amount.is_zero() {
+            res = res.add_message(asset_transfer_msg(fee_asset, &fee.collector, fee_amount)?);
+        }
+
+        res = res
+            .add_attribute("fee_asset", fee_asset.to_string())
+            .add_attribute("gross_amount", gross)
+            .add_attribute("fee_amount", fee_amount)
+            .add_attribute("net_amount", net);
+    }
+
+    // Forward payment to proceeds_recipients, stays in this contract if none are set
+    for (recipient, amount) in calc_proceeds_split(&config.proceeds_recipients, net_cost)? {
+        if !amount.is_zero() {
+            res = res.add_message(asset_transfer_msg(&config.cost_asset, &recipient, amount)?);
+        }
+        res = res.add_attribute(format!("proceeds_{}", recipient), amount);
+    }
+
+    Ok(res)
+}
+
+// A failed purchase reverts all state, so breaches are counted here instead
+// Anyone (usually a keeper) can call this, PAUSED is set after price_guards.max_breaches in a row
+pub fn check_price(
+    mut deps: DepsMut,
+    env: Env
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    let current_price = query_price(deps.as_ref(), &config, env.block.time)?;
+    let last_price = LAST_PRICE.may_load(deps.storage)?;
+
+    match price_guard_check(&config.price_guards, last_price.as_ref(), &current_price, env.block.time) {
+        Ok(()) => {
+            accept_price(deps.storage, current_price.price, env.block.time)?;
+            record_twap_snapshot(deps.branch(), &config)?;
+
+            Ok(Response::new()
+                .add_attribute("Call", "Check price")
+                .add_attribute("price", current_price.price)
+                .add_attribute("breach", "false"))
+        },
+        Err(e @ (ContractError::StalePrice { .. } | ContractError::StaleAnchor { .. } | ContractError::PriceDeviation { .. })) => {
+            let breaches = PRICE_BREACHES.update(
+                deps.storage,
+                |old| -> StdResult<u32> {
+                    Ok(old.saturating_add(1))
+                }
+            )?;
+
+            let max_breaches = config.price_guards.max_breaches;
+            let pause = max_breaches != 0 && breaches >= max_breaches;
+
+            if pause {
+                PAUSED.save(deps.storage, &true)?;
+            }
+
+            Ok(Response::new()
+                .add_attribute("Call", "Check price")
+                .add_attribute("breach", "true")
+                .add_attribute("reason", e.to_string())
+                .add_attribute("consecutive_breaches", breaches.to_string())
+                .add_attribute("paused", pause.to_string()))
+        },
+        Err(e) => Err(e),
+    }
+}
+
+// Takes the current price as LAST_PRICE without the deviation check, after a real move or a stale anchor
+pub fn reset_price_anchor(
+    deps: DepsMut,
+    env: Env,
+    sender: &Addr
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.admin.as_ref() != Some(sender) {
+        return Err(ContractError::Unauthorized);
+    }
+
+    let current_price = query_price(deps.as_ref(), &config, env.block.time)?;
+
+    // The source itself still has to be fresh
+    price_guard_check(&config.price_guards, None, &current_price, env.block.time)?;
+
+    accept_price(deps.storage, current_price.price, env.block.time)?;
+
+    Ok(Response::new()
+        .add_attribute("Call", "Reset price anchor")
+        .add_attribute("price", current_price.price))
+}
+
+// Stores a price that passed the guards and resets the breach count
+fn accept_price(
+    storage: &mut dyn Storage,
+    price: Uint128,
+    now: Timestamp
+) -> Result<(), ContractError> {
+
+    LAST_PRICE.save(storage, &LastPrice { price, timestamp: now })?;
+    PRICE_BREACHES.save(storage, &0_u32)?;
+
+    Ok(())
+}
+
+pub fn claim_available(
+    deps: DepsMut,
+    env: Env,
+    user_wallet: Addr,
+    uuid: u64
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+    let Some(purchase) = PURCHASES.may_load(deps.storage, (user_wallet.clone(), uuid))? else {
+        return Err(ContractError::PurchaseNotFound { uuid });
+    };
+
+    // First - Do checks
+    claim_vestable_checks(&purchase, env.block.height)?;
+
+    // Second - Calculate amount to be vested & sent
+    let (amount_vestable, close_purchase) =
+        calc_amt_vested(&purchase, env.block.height)?;
+
+    if amount_vestable.is_zero() {
+        return Err(ContractError::NothingToClaim);
+    }
+
+    let remaining = record_claim(
+        deps.storage,
+        &user_wallet,
+        uuid,
+        &purchase,
+        amount_vestable,
+        close_purchase,
+        env.block.height
+    )?;
+
+    // Third - Send vested tokens to owner
+    let send_msg = asset_transfer_msg(&config.beingsold_asset, &user_wallet, amount_vestable)?;
+
+    Ok(Response::new()
+        .add_message(send_msg)
+        .add_attribute("Call", "Claim vestable")
+        .add_attribute("uuid", uuid.to_string())
+        .add_attribute("amount", amount_vestable)
+        .add_attribute("remaining", remaining))
+}
+
+pub fn claim_all(
+    deps: DepsMut,
+    env: Env,
+    user_wallet: Addr,
+    limit: Option<u32>
+) -> Result<Response, ContractError> {
+
+    let config: Config = CONFIG.load(deps.storage)?;
+    let limit = limit.unwrap_or(u32::MAX) as usize;
+
+    let purchases: Vec<(u64, Purchase)> = PURCHASES
+        .prefix(user_wallet.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut total_claimed = Uint128::zero();
+    let mut response = Response::new().add_attribute("Call", "Claim all");
+
+    for (uuid, purchase) in purchases {
+        // Positions with nothing claimable are skipped rather than failing the whole claim, any other error still fails
+        match claim_vestable_checks(&purchase, env.block.height) {
+            Ok(()) => {},
+            Err(ContractError::NothingToClaim) => continue,
+            Err(e) => return Err(e),
+        }
+
+        let (amount_vestable, close_purchase) = match calc_amt_vested(&purchase, env.block.height) {
+            Ok((amount, close)) if !amount.is_zero() => (amount, close),
+            Ok(_) | Err(ContractError::NothingToClaim) => continue,
+            Err(e) => return Err(e),
+        };
+
+        let remaining = record_claim(
+            deps.storage,
+            &user_wallet,
+            uuid,
+            &purchase,
+            amount_vestable,
+            close_purchase,
+            env.block.height
+        )?;
+
+        total_claimed = total_claimed.checked_add(amount_vestable).map_err(StdError::from)?;
+
+        response = response
+            .add_attribute(format!("claimed_{}", uuid), amount_vestable)
+            .add_attribute(format!("remaining_{}", uuid), remaining);
+    }
+
+    if total_claimed.is_zero() {
+        return Err(ContractError::NothingToClaim);
+    }
+
+    let send_msg = asset_transfer_msg(&config.beingsold_asset, &user_wallet, total_claimed)?;
+
+    Ok(response
+        .add_message(send_msg)
+        .add_attribute("amount", total_claimed))
+}
+
+// Updates or removes a purchase after a claim, returns the amount left to be claimed
+fn record_claim(
+    storage: &mut dyn Storage,
+    user_wallet: &Addr,
+    uuid: u64,
+    purchase: &Purchase,
+    amount_vestable: Uint128,
+    close_purchase: bool,
+    current_block: u64
+) -> Result<Uint128, ContractError> {
+
+    let remaining = purchase.amount_purchased
+        .checked_sub(purchase.already_claimed)
+        .and_then(|left| left.checked_sub(amount_vestable))
+        .map_err(StdError::from)?;
+
+    LIABILITY.update(
+        storage,
+        |o| -> StdResult<Uint128> {
+            o.checked_sub(amount_vestable).map_err(|e| e.into())
+        }
+    )?;
+
+    STATS.update(storage, |mut stats| -> StdResult<Stats> {
+        stats.total_claimed = stats.total_claimed.checked_add(amount_vestable)?;
+        if close_purchase {
+            stats.active_positions = stats.active_positions.saturating_sub(1);
+        }
+        Ok(stats)
+    })?;
+
+    if close_purchase {
+        PURCHASES.remove(
+            storage,
+            (user_wallet.clone(), uuid)
+        );
+    } else {
+        PURCHASES.update(
+            storage,
             (user_wallet.clone(), uuid),
             |old| -> StdResult<Purchase> {
                 let Some(oldx) = old else {
@@ -289,7 +1461,7 @@ pub fn claim_available(
                 };
                 Ok(Purchase {
                     already_claimed: oldx.already_claimed.checked_add(amount_vestable)?,
-                    last_claim: env.block.height,
+                    last_claim: current_block,
                     //closed: close_purchase,
                     ..oldx
                 })
@@ -297,7 +1469,7 @@ pub fn claim_available(
         )?;
     }
 
-    Ok(Response::new().add_attribute("Call", "Claim vestable"))
+    Ok(remaining)
 }
 
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -307,9 +1479,21 @@ pub fn claim_available(
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
-        QueryMsg::GetAdmin {} => to_binary(&get_admin(deps)?),
-        QueryMsg::GetConfig {} => to_binary(&get_config(deps)?),
-        QueryMsg::GetUserPurchaseIds { user_wallet } => to_binary(&get_user_purchase_ids(deps, user_wallet)?),
-        QueryMsg::GetUserPurchaseInfos { user_wallet } => to_binary(&get_user_purchase_infos(deps, user_wallet)?)
+        QueryMsg::GetAdmin {} => to_json_binary(&get_admin(deps)?),
+        QueryMsg::GetPendingAdmin {} => to_json_binary(&get_pending_admin(deps)?),
+        QueryMsg::GetRoles { address } => to_json_binary(&get_roles(deps, address)?),
+        QueryMsg::GetConfig {} => to_json_binary(&get_config(deps)?),
+        QueryMsg::GetBonus { vesting_period } => to_json_binary(&get_bonus(deps, env, vesting_period)?),
+        QueryMsg::GetDebtRatio {} => to_json_binary(&get_debt_ratio(deps, env)?),
+        QueryMsg::GetQueuedChanges { start_after, limit } => to_json_binary(&get_queued_changes(deps, start_after, limit)?),
+        QueryMsg::GetLastPrice {} => to_json_binary(&get_last_price(deps)?),
+        QueryMsg::SimulatePurchase { cost_amount, vesting_period } => to_json_binary(&get_simulate_purchase(deps, env, cost_amount, vesting_period)?),
+        QueryMsg::GetUserPurchaseIds { user_wallet, start_after, limit } => to_json_binary(&get_user_purchase_ids(deps, user_wallet, start_after, limit)?),
+        QueryMsg::GetUserPurchaseInfos { user_wallet, start_after, limit } => to_json_binary(&get_user_purchase_infos(deps, user_wallet, start_after, limit)?),
+        QueryMsg::GetClaimable { user, uuid } => to_json_binary(&get_claimable(deps, env, user, uuid)?),
+        QueryMsg::GetTotalClaimable { user } => to_json_binary(&get_total_claimable(deps, env, user)?),
+        QueryMsg::GetStats {} => to_json_binary(&get_stats(deps)?),
+        QueryMsg::AuditSolvency {} => to_json_binary(&get_audit_solvency(deps, env)?),
+        QueryMsg::GetAllPurchases { start_after, limit } => to_json_binary(&get_all_purchases(deps, start_after, limit)?)
     }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Decimal, StdError, Timestamp, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,9 +9,171 @@ pub enum ContractError {
     #[error("Unauthorized")]
     Unauthorized,
 
-    #[error("Generic Error: {0}")]
-    GenericError(String),
+    #[error("Overflow | {context}")]
+    Overflow { context: String },
 
-    #[error("To Do Error")]
-    ToDo,
+    //~~~~~~~~~~~~~~
+    // Funds
+    //~~~~~~~~~~~~~~
+    #[error("No funds sent")]
+    NoFunds,
+
+    #[error("More than 1 coin sent")]
+    MultipleCoins,
+
+    #[error("Wrong denom | expected: {expected} | got: {got}")]
+    WrongDenom { expected: String, got: String },
+
+    #[error("{asset} is cw20, use Send")]
+    UseCw20Send { asset: String },
+
+    #[error("Native denom can't be empty")]
+    EmptyDenom,
+
+    #[error("beingsold_asset and cost_asset are both {asset}")]
+    SameAsset { asset: String },
+
+    #[error("Amount is zero")]
+    ZeroAmount,
+
+    //~~~~~~~~~~~~~~
+    // Purchase
+    //~~~~~~~~~~~~~~
+    #[error("Purchasing is paused")]
+    PurchasingPaused,
+
+    #[error("Vesting period too long | max: {max} | got: {got}")]
+    VestingPeriodTooLong { max: u8, got: u128 },
+
+    #[error("Vesting period too short | min: {min} | got: {got}")]
+    VestingPeriodTooShort { min: u8, got: u128 },
+
+    #[error("Purchase amount too high | max: {max} | got: {got}")]
+    PurchaseAmountTooHigh { max: Uint128, got: Uint128 },
+
+    #[error("Purchase amount too low | min: {min} | got: {got}")]
+    PurchaseAmountTooLow { min: Uint128, got: Uint128 },
+
+    #[error("Amount not rounded to 3rd decimal | got: {got}")]
+    NotRoundedToThirdDecimal { got: Uint128 },
+
+    #[error("Too many active purchases | max: {max}")]
+    TooManyPositions { max: u32 },
+
+    #[error("Insufficient inventory | available: {available} | requested: {requested}")]
+    InsufficientInventory { available: Uint128, requested: Uint128 },
+
+    #[error("Insufficient proceeds | available: {available} | requested: {requested}")]
+    InsufficientProceeds { available: Uint128, requested: Uint128 },
+
+    #[error("Nothing to claim")]
+    NothingToClaim,
+
+    #[error("Purchase not found | uuid: {uuid}")]
+    PurchaseNotFound { uuid: u64 },
+
+    #[error("Inventory deficit | holdings: {holdings} | required: {required}")]
+    InventoryDeficit { holdings: Uint128, required: Uint128 },
+
+    #[error("No surplus to sync into inventory")]
+    NoSurplus,
+
+    //~~~~~~~~~~~~~~
+    // Price
+    //~~~~~~~~~~~~~~
+    #[error("Stale price | age: {age} | max_age: {max_age}")]
+    StalePrice { age: u64, max_age: u64 },
+
+    #[error("Last accepted price too old, admin must ResetPriceAnchor | age: {age} | max_age: {max_age}")]
+    StaleAnchor { age: u64, max_age: u64 },
+
+    #[error("Price deviation too large | last: {last} | current: {current}")]
+    PriceDeviation { last: Uint128, current: Uint128 },
+
+    #[error("Not enough price sources responded | responded: {responded} | required: {required}")]
+    NotEnoughPriceSources { responded: u32, required: u32 },
+
+    #[error("Price query failed | contract: {contract} | error: {error}")]
+    PriceQueryFailed { contract: String, error: String },
+
+    #[error("Zero price | contract: {contract}")]
+    ZeroPrice { contract: String },
+
+    #[error("Not enough TWAP history | window: {window}")]
+    NotEnoughTwapHistory { window: u64 },
+
+    #[error("Invalid TWAP | {reason}")]
+    InvalidTwap { reason: String },
+
+    #[error("Price source is not Median")]
+    PriceSourceNotMedian,
+
+    #[error("Price source not found | contract: {contract}")]
+    PriceSourceNotFound { contract: String },
+
+    //~~~~~~~~~~~~~~
+    // Config
+    //~~~~~~~~~~~~~~
+    #[error("Invalid limits | {reason}")]
+    InvalidLimits { reason: String },
+
+    #[error("Invalid discount curve | {reason}")]
+    InvalidDiscountCurve { reason: String },
+
+    #[error("Invalid price source | {reason}")]
+    InvalidPriceSource { reason: String },
+
+    #[error("Invalid price guards | {reason}")]
+    InvalidPriceGuards { reason: String },
+
+    #[error("Invalid proceeds recipients | {reason}")]
+    InvalidProceedsRecipients { reason: String },
+
+    #[error("Proceeds shares must sum to 1 | total: {total}")]
+    ProceedsSharesNotOne { total: Decimal },
+
+    #[error("Fee bps must be between 1 and 9_999 | got: {bps}")]
+    InvalidFeeBps { bps: u16 },
+
+    //~~~~~~~~~~~~~~
+    // Admin
+    //~~~~~~~~~~~~~~
+    #[error("No pending admin proposal")]
+    NoPendingAdmin,
+
+    #[error("Admin proposal expiry already passed | expiry: {expiry}")]
+    ExpiryInPast { expiry: Timestamp },
+
+    #[error("Admin proposal expired | expiry: {expiry}")]
+    AdminProposalExpired { expiry: Timestamp },
+
+    #[error("{address} already has role {role}")]
+    RoleAlreadyGranted { address: String, role: String },
+
+    #[error("{address} doesn't have role {role}")]
+    RoleNotGranted { address: String, role: String },
+
+    #[error("Timelocked for {blocks} blocks, use QueueConfigChange")]
+    Timelocked { blocks: u64 },
+
+    #[error("No queued change | id: {id}")]
+    QueuedChangeNotFound { id: u64 },
+
+    #[error("Queued change not active yet | id: {id} | activation_height: {activation_height}")]
+    QueuedChangeNotReady { id: u64, activation_height: u64 },
+
+    //~~~~~~~~~~~~~~
+    // Migrate
+    //~~~~~~~~~~~~~~
+    #[error("Wrong contract | expected: {expected} | got: {got}")]
+    WrongContract { expected: String, got: String },
+
+    #[error("Invalid version | {version}")]
+    InvalidVersion { version: String },
+
+    #[error("Can't downgrade | from: {from} | to: {to}")]
+    CannotDowngrade { from: String, to: String },
+
+    #[error("Migrating from {version} needs {field}")]
+    MissingMigrateField { field: String, version: String },
 }
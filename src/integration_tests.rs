@@ -1,14 +1,13 @@
 #![cfg(test)]
 use core::fmt::Display;
 use anyhow::ensure;
-use std::ops::Add;
 
-use cosmwasm_std::{coins, to_binary, Addr, Binary, Coin, Empty, Uint128, coin};
+use cosmwasm_std::{coins, Addr, Coin, Empty, Uint128, coin};
 
 // use self::create_contract::*;
 // use self::create_users::*;
 // use self::init_contracts::init_all_contracts;
-use crate::{msg::*, state::*, integration_tests::setup_users::fake_user};
+use crate::{msg::*, integration_tests::setup_users::fake_user};
 
 use cw_multi_test::{App, Contract, ContractWrapper, Executor};
 //use self::create_contract::*;
@@ -42,7 +41,7 @@ pub mod setup_users {
     pub fn fake_user(name: String) -> User {
         User {
             name: name.clone(),
-            address: Addr::unchecked(format!("{}", name)),
+            address: Addr::unchecked(name),
         }
     }
 
@@ -79,9 +78,266 @@ pub mod setup_users {
     }
 }
 
+pub mod mock_oracle {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{
+        to_json_binary, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdResult, Uint128,
+    };
+    use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+    use cw_storage_plus::Item;
+    use crate::msg::{OracleQueryMsg, OraclePriceResponse};
+    use cosmwasm_std::Addr;
+
+    // 5_000_000 (5 USDC per JUNO)
+    pub const INITIAL_PRICE: u128 = 5_000_000_u128;
+
+    const PRICE: Item<OraclePriceResponse> = Item::new("price");
+
+    #[cw_serde]
+    pub struct InstantiateMsg {
+        pub price: Uint128,
+    }
+
+    #[cw_serde]
+    pub enum ExecuteMsg {
+        SetPrice { price: Uint128 },
+    }
+
+    pub fn instantiate(
+        deps: DepsMut,
+        env: Env,
+        _info: MessageInfo,
+        msg: InstantiateMsg,
+    ) -> StdResult<Response> {
+        PRICE.save(
+            deps.storage,
+            &OraclePriceResponse { price: msg.price, last_updated: env.block.time },
+        )?;
+        Ok(Response::new())
+    }
+
+    pub fn execute(
+        deps: DepsMut,
+        env: Env,
+        _info: MessageInfo,
+        msg: ExecuteMsg,
+    ) -> StdResult<Response> {
+        match msg {
+            ExecuteMsg::SetPrice { price } => {
+                PRICE.save(
+                    deps.storage,
+                    &OraclePriceResponse { price, last_updated: env.block.time },
+                )?;
+                Ok(Response::new())
+            }
+        }
+    }
+
+    pub fn query(deps: Deps, _env: Env, msg: OracleQueryMsg) -> StdResult<Binary> {
+        match msg {
+            OracleQueryMsg::Price { .. } => to_json_binary(&PRICE.load(deps.storage)?),
+        }
+    }
+
+    pub fn oracle_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(execute, instantiate, query);
+        Box::new(contract)
+    }
+
+    pub fn init_oracle(router: &mut App, owner: &Addr, price: u128) -> Addr {
+        let oracle_id = router.store_code(oracle_contract());
+        router
+            .instantiate_contract(
+                oracle_id,
+                owner.clone(),
+                &InstantiateMsg { price: Uint128::from(price) },
+                &[],
+                "mock_oracle",
+                None,
+            )
+            .unwrap()
+    }
+
+    pub fn set_price(router: &mut App, owner: &Addr, oracle: &Addr, price: u128) {
+        router
+            .execute_contract(
+                owner.clone(),
+                oracle.clone(),
+                &ExecuteMsg::SetPrice { price: Uint128::from(price) },
+                &[],
+            )
+            .unwrap();
+    }
+}
+
+pub mod mock_pair {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{
+        to_json_binary, Addr, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdResult,
+        Timestamp, Uint128,
+    };
+    use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+    use cw_storage_plus::Item;
+    use crate::msg::{CumulativePriceResponse, PairQueryMsg};
+
+    #[cw_serde]
+    pub struct PairState {
+        pub price: Uint128,
+        pub price_cumulative: Uint128,
+        pub last_update: Timestamp,
+    }
+
+    const STATE: Item<PairState> = Item::new("state");
+
+    #[cw_serde]
+    pub struct InstantiateMsg {
+        pub price: Uint128,
+    }
+
+    #[cw_serde]
+    pub enum ExecuteMsg {
+        // Simulates a swap moving the pool price
+        SetPrice { price: Uint128 },
+    }
+
+    // Accumulates the current price up to now, the way a pair does on every swap
+    fn accumulate(state: &PairState, now: Timestamp) -> Uint128 {
+        let elapsed = now.seconds().saturating_sub(state.last_update.seconds());
+        state.price_cumulative + state.price * Uint128::from(elapsed)
+    }
+
+    pub fn instantiate(
+        deps: DepsMut,
+        env: Env,
+        _info: MessageInfo,
+        msg: InstantiateMsg,
+    ) -> StdResult<Response> {
+        STATE.save(
+            deps.storage,
+            &PairState {
+                price: msg.price,
+                price_cumulative: Uint128::zero(),
+                last_update: env.block.time,
+            },
+        )?;
+        Ok(Response::new())
+    }
+
+    pub fn execute(
+        deps: DepsMut,
+        env: Env,
+        _info: MessageInfo,
+        msg: ExecuteMsg,
+    ) -> StdResult<Response> {
+        match msg {
+            ExecuteMsg::SetPrice { price } => {
+                let state = STATE.load(deps.storage)?;
+                STATE.save(
+                    deps.storage,
+                    &PairState {
+                        price,
+                        price_cumulative: accumulate(&state, env.block.time),
+                        last_update: env.block.time,
+                    },
+                )?;
+                Ok(Response::new())
+            }
+        }
+    }
+
+    pub fn query(deps: Deps, env: Env, msg: PairQueryMsg) -> StdResult<Binary> {
+        match msg {
+            PairQueryMsg::CumulativePrice { .. } => {
+                let state = STATE.load(deps.storage)?;
+                to_json_binary(&CumulativePriceResponse {
+                    price_cumulative: accumulate(&state, env.block.time),
+                    timestamp: env.block.time,
+                })
+            }
+        }
+    }
+
+    pub fn pair_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(execute, instantiate, query);
+        Box::new(contract)
+    }
+
+    pub fn init_pair(router: &mut App, owner: &Addr, price: u128) -> Addr {
+        let pair_id = router.store_code(pair_contract());
+        router
+            .instantiate_contract(
+                pair_id,
+                owner.clone(),
+                &InstantiateMsg { price: Uint128::from(price) },
+                &[],
+                "mock_pair",
+                None,
+            )
+            .unwrap()
+    }
+
+    pub fn set_price(router: &mut App, owner: &Addr, pair: &Addr, price: u128) {
+        router
+            .execute_contract(
+                owner.clone(),
+                pair.clone(),
+                &ExecuteMsg::SetPrice { price: Uint128::from(price) },
+                &[],
+            )
+            .unwrap();
+    }
+}
+
+pub mod setup_cw20 {
+    use cosmwasm_std::{Addr, Empty, Uint128};
+    use cw20::{Cw20Coin, Cw20QueryMsg, BalanceResponse};
+    use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+
+    pub fn cw20_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            cw20_base::contract::execute,
+            cw20_base::contract::instantiate,
+            cw20_base::contract::query,
+        );
+        Box::new(contract)
+    }
+
+    pub fn init_cw20(
+        router: &mut App,
+        owner: &Addr,
+        symbol: &str,
+        balances: Vec<(&Addr, u128)>,
+    ) -> Addr {
+        let cw20_id = router.store_code(cw20_contract());
+        let msg = cw20_base::msg::InstantiateMsg {
+            name: format!("{} token", symbol),
+            symbol: symbol.to_string(),
+            decimals: 6,
+            initial_balances: balances
+                .into_iter()
+                .map(|(address, amount)| Cw20Coin {
+                    address: address.to_string(),
+                    amount: Uint128::from(amount),
+                })
+                .collect(),
+            mint: None,
+            marketing: None,
+        };
+
+        router.instantiate_contract(cw20_id, owner.clone(), &msg, &[], symbol, None).unwrap()
+    }
+
+    pub fn cw20_balance(router: &App, token: &Addr, address: &Addr) -> Uint128 {
+        let res: BalanceResponse = router
+            .wrap()
+            .query_wasm_smart(token, &Cw20QueryMsg::Balance { address: address.to_string() })
+            .unwrap();
+        res.balance
+    }
+}
+
 pub mod setup_contract {
     use super::*;
-    use std::marker::PhantomData;
     use std::borrow::BorrowMut;
     use super::{NATIVE_JUNO, NATIVE_USDC};
 
@@ -90,24 +346,56 @@ pub mod setup_contract {
             crate::contract::execute,
             crate::contract::instantiate,
             crate::contract::query,
-        );
+        )
+        .with_migrate(crate::contract::migrate)
+        .with_sudo(crate::contract::sudo);
         Box::new(contract)
     }
 
 
+    pub fn default_instantiate_msg(oracle: &Addr) -> InstantiateMsg {
+        InstantiateMsg {
+            admin: None,
+            beingsold_asset: crate::state::AssetInfo::Native { denom: NATIVE_JUNO.to_string() },
+            cost_asset: crate::state::AssetInfo::Native { denom: NATIVE_USDC.to_string() },
+            price_source: crate::state::PriceSource::Oracle { contract: oracle.clone() },
+            price_guards: crate::state::PriceGuards {
+                // 1 year, tests fast forward weeks at a time without updating the oracle
+                max_price_age: 31_536_000,
+                max_deviation: cosmwasm_std::Decimal::percent(50),
+                max_breaches: 3,
+            },
+            limits: crate::state::SaleLimits {
+                min_purchase_amount: Uint128::zero(),
+                max_purchase_amount: Uint128::from(500_000_000_u128),
+                min_vest: 0,
+                max_vest: 52,
+                blocks_in_week: 100_000,
+                max_positions: 5,
+            },
+            // 1% per week
+            discount_curve: crate::state::DiscountCurve::Linear {
+                slope: cosmwasm_std::Decimal::percent(1),
+            },
+            // proceeds stay in the contract
+            proceeds_recipients: vec![],
+            purchase_fee: None,
+            timelock_blocks: 0,
+        }
+    }
+
+
     pub fn init_cpbond(
         router: &mut App,
         admin: &Addr,
+        msg: &InstantiateMsg,
     ) -> Addr {
         let cpbond_id = router.store_code(cpbond_contract());
-        let msg = InstantiateMsg {
-            admin: None,
-            beingsold_denom: NATIVE_JUNO.to_string(),
-            cost_denom: NATIVE_USDC.to_string()
-        };
 
         let addr =
-            router.instantiate_contract(cpbond_id, admin.clone(), &msg, &[], "cp_bond", None).unwrap();
+            router
+                .instantiate_contract(cpbond_id, admin.clone(), msg, &[], "cp_bond", Some(admin.to_string()))
+                .unwrap();
 
         println!("cp_bond | Addr: {:?}", addr);
 
@@ -115,16 +403,29 @@ pub mod setup_contract {
     }
 
 
-    pub fn give_juno_to_contract<'a>(cpbond_contract: &Addr, router: &'a mut App) -> &'a mut App {
+    pub fn give_juno_to_contract<'a>(
+        cpbond_contract: &Addr,
+        admin: &Addr,
+        router: &'a mut App
+    ) -> &'a mut App {
         let juno = cosmwasm_std::coin(1_000_000_000, NATIVE_JUNO);
 
         router.borrow_mut().init_modules(|router, _, storage| {
             router
                 .bank
-                .init_balance(storage, cpbond_contract, vec![juno])
+                .init_balance(storage, admin, vec![juno.clone()])
                 .unwrap()
         });
 
+        router
+            .execute_contract(
+                admin.clone(),
+                cpbond_contract.clone(),
+                &ExecuteMsg::AddSaleFunds {},
+                &[juno],
+            )
+            .unwrap();
+
         router
     }
 
@@ -134,30 +435,152 @@ pub mod setup_contract {
         admin: &Addr
     ) -> (&'a mut App, Addr) {
 
-        let cpbond = init_cpbond(router, admin);
-
-        let r = give_juno_to_contract(&cpbond, router);
+        let (r, cpbond, _oracle) = setup_with_oracle(router, admin);
 
         (r, cpbond)
     }
 
 
+    pub fn setup_with_oracle<'a>(
+        router: &'a mut App,
+        admin: &Addr
+    ) -> (&'a mut App, Addr, Addr) {
+
+        setup_custom(router, admin, |_| {})
+    }
+
+
+    // Same as setup_with_oracle, edit_msg can change the InstantiateMsg before instantiate
+    pub fn setup_custom<'a>(
+        router: &'a mut App,
+        admin: &Addr,
+        edit_msg: impl FnOnce(&mut InstantiateMsg),
+    ) -> (&'a mut App, Addr, Addr) {
+
+        let oracle = super::mock_oracle::init_oracle(router, admin, super::mock_oracle::INITIAL_PRICE);
+
+        let mut msg = default_instantiate_msg(&oracle);
+        edit_msg(&mut msg);
+
+        let cpbond = init_cpbond(router, admin, &msg);
+
+        let r = give_juno_to_contract(&cpbond, admin, router);
+
+        r.execute_contract(
+            admin.clone(),
+            cpbond.clone(),
+            &ExecuteMsg::ResumePurchasing {},
+            &[],
+        )
+        .unwrap();
+
+        (r, cpbond, oracle)
+    }
+
+
+}
+
+// Stand-ins for older code, same contract but stored state as an earlier version would leave it
+pub mod setup_legacy {
+    use super::*;
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{to_json_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
+    use crate::ContractError;
+    use crate::state::{LegacyPurchase, BALANCE, LEGACY_PURCHASES, PAUSED, PURCHASE_COUNT};
+
+    const CONTRACT_NAME: &str = "crates.io:cpbond";
+
+    // 0.1.0 store with live positions, uuids handed out in order from 1
+    #[cw_serde]
+    pub struct V0_1InstantiateMsg {
+        pub admin: String,
+        pub beingsold_denom: String,
+        pub cost_denom: String,
+        pub balance: Uint128,
+        pub purchases: Vec<(String, LegacyPurchase)>,
+    }
+
+    // Writes the store the way 0.1.0 left it | plain denom Config, Purchase without vest_start
+    fn v0_1_instantiate(
+        deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        msg: V0_1InstantiateMsg,
+    ) -> Result<Response, ContractError> {
+        cw2::set_contract_version(deps.storage, CONTRACT_NAME, "0.1.0")?;
+
+        // Raw bytes so the test doesn't lean on LegacyConfig
+        let config = format!(
+            r#"{{"admin":"{}","beingsold_denom":"{}","cost_denom":"{}"}}"#,
+            msg.admin, msg.beingsold_denom, msg.cost_denom
+        );
+        deps.storage.set(b"cp_b_config", config.as_bytes());
+
+        PAUSED.save(deps.storage, &false)?;
+        BALANCE.save(deps.storage, &msg.balance)?;
+
+        let mut uuid = 1_u64;
+        for (wallet, purchase) in msg.purchases {
+            LEGACY_PURCHASES.save(deps.storage, (deps.api.addr_validate(&wallet)?, uuid), &purchase)?;
+            uuid += 1;
+        }
+        PURCHASE_COUNT.save(deps.storage, &uuid)?;
+
+        Ok(Response::new())
+    }
+
+    fn v0_1_execute(
+        _deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        _msg: Empty,
+    ) -> Result<Response, ContractError> {
+        Err(ContractError::Unauthorized)
+    }
+
+    fn v0_1_query(_deps: Deps, _env: Env, _msg: Empty) -> StdResult<Binary> {
+        to_json_binary(&Empty {})
+    }
+
+    // Newer than this code, migrating to it is a downgrade
+    fn v9_instantiate(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: InstantiateMsg,
+    ) -> Result<Response, ContractError> {
+        let res = crate::contract::instantiate(deps.branch(), env, info, msg)?;
+        cw2::set_contract_version(deps.storage, CONTRACT_NAME, "9.0.0")?;
+        Ok(res)
+    }
+
+    pub fn cpbond_v0_1_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            v0_1_execute,
+            v0_1_instantiate,
+            v0_1_query,
+        );
+        Box::new(contract)
+    }
+
+    pub fn cpbond_v9_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            crate::contract::execute,
+            v9_instantiate,
+            crate::contract::query,
+        );
+        Box::new(contract)
+    }
 }
 
 #[test]
 fn test_setup() -> Result<(), anyhow::Error> {
-    use std::borrow::BorrowMut;
-    use crate::utils::*;
-    use anyhow::Result;
-    use cw_multi_test::AppResponse;
-    use std::ops::{Add, Sub};
-
     //~~~~~~~~~~~~~~~~~~~~
     // Setup
     //~~~~~~~~~~~~~~~~~~~~
     let mut router = App::default();
     // Users
-    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
     // Contract
     let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
 
@@ -182,31 +605,132 @@ fn test_setup() -> Result<(), anyhow::Error> {
 
 #[test]
 fn test_purchase() -> Result<(), anyhow::Error> {
-    use std::borrow::BorrowMut;
-    use crate::utils::*;
     use anyhow::Result;
     use cw_multi_test::AppResponse;
-    use std::ops::{Add, Sub};
+    use crate::error::ContractError;
 
     //~~~~~~~~~~~~~~~~~~~~
     // Setup
     //~~~~~~~~~~~~~~~~~~~~
     let mut router = App::default();
     // Users
-    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
     // Contract
     let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
 
 
     // First test, have a user buy 10 USDCX with a 10 week vesting period
+    let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 };
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
 
-    // Make sure that the total they get is 1.1 JUNO
+    // Make sure that the total they get is ~2.2 JUNO (10 USDC / 5.001 USDC per JUNO * 1.1)
+    // price is rounded up to the 3rd decimal in calc_juno_amt
+    let q = crate::msg::QueryMsg::GetUserPurchaseInfos { user_wallet: john.address.to_string(), start_after: None, limit: None };
+    let qrez: crate::query::UserPurchaseInfos = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+    let (uuid, purchase) = qrez.purchase_infos[0].clone();
+    ensure!(
+        (purchase.amount_purchased == Uint128::from(2_199_560_u128)),
+        here(format!("amount purchased: {}", purchase.amount_purchased), line!(), column!())
+    );
 
     // Increase the block height and test to make sure the claims work
+    // 1 week = 100_000 blocks, 2_199_560 / 1_000_000 blocks = 2 ujunox per block
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 100_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(600_000);
+    });
 
-    // 
+    let claim_msg = crate::msg::ExecuteMsg::ClaimAvailable { uuid };
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &claim_msg,
+        &[]
+    );
+    ensure!(res.is_ok(), here(format!("john partial claim {:#?}", res), line!(), column!()));
+
+    let claim_res = res.unwrap();
+    let wasm_event = claim_res.events.iter().find(|e| e.ty == "wasm").unwrap();
+    let attr = |key: &str| {
+        wasm_event.attributes.iter().find(|a| a.key == key).map(|a| a.value.clone())
+    };
+    ensure!(
+        (attr("uuid") == Some(uuid.to_string())
+            && attr("amount") == Some("200000".to_string())
+            && attr("remaining") == Some("1999560".to_string())),
+        here(format!("claim attributes: {:#?}", wasm_event.attributes), line!(), column!())
+    );
 
+    let john_juno: Coin = router.wrap().query_balance(john.address.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (john_juno.amount == Uint128::from(200_000_u128)),
+        here(format!("john juno after partial claim: {}", john_juno.amount), line!(), column!())
+    );
+
+    // Claiming again in the same block fails
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &claim_msg,
+        &[]
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NothingToClaim))
+        ),
+        here(format!("Claim twice in one block should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Fast forward past expiration, final claim pays out everything left
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 1_000_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(6_000_000);
+    });
 
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &claim_msg,
+        &[]
+    );
+    ensure!(res.is_ok(), here(format!("john final claim {:#?}", res), line!(), column!()));
+
+    let john_juno: Coin = router.wrap().query_balance(john.address.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (john_juno.amount == Uint128::from(2_199_560_u128)),
+        here(format!("john juno after final claim: {}", john_juno.amount), line!(), column!())
+    );
+
+    let contract_juno: Coin =
+        router.wrap().query_balance(cpbond_contract.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (contract_juno.amount == Uint128::from(997_800_440_u128)),
+        here(format!("contract juno after final claim: {}", contract_juno.amount), line!(), column!())
+    );
+
+    // Purchase is closed once fully claimed
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &claim_msg,
+        &[]
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PurchaseNotFound { uuid: 1 }))
+        ),
+        here(format!("Claim on closed purchase should've failed: {:#?}", res), line!(), column!())
+    );
 
     Ok(())
 
@@ -217,7 +741,7 @@ fn test_purchase() -> Result<(), anyhow::Error> {
 // Tests
 
 // ~~~~~~~ Purchasing High Level ~~~~~~~~ //
-// (X) - CHECK: purchase doesn't go through if vest period > MAX_VEST
+// (X) - CHECK: purchase doesn't go through if vest period > max_vest
 // (X) - CHECK: purchase fails if denom send != cost_denom
 // (X) - CHECK: purchase fails if coins sent == empty
 // (X) - CHECK: purchase fails if >1 coin sent
@@ -226,24 +750,23 @@ fn test_purchase() -> Result<(), anyhow::Error> {
 #[test]
 fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
     use std::borrow::BorrowMut;
-    use crate::utils::*;
     use anyhow::Result;
     use cw_multi_test::AppResponse;
-    use std::ops::{Add, Sub};
+    use crate::error::ContractError;
 
     //~~~~~~~~~~~~~~~~~~~~
     // Setup
     //~~~~~~~~~~~~~~~~~~~~
     let mut router = App::default();
     // Users
-    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
     // Contract
     let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
 
 
     // contract has 1_000_000_000 JUNO (1,000 JUNO)
     // each user has 100_000_000 USDC (100 USDC)
-    // fake price is 5_000_000 (5 USDC per JUNO)
+    // oracle price is 5_000_000 (5 USDC per JUNO)
     // max_purchase is 500_000_000 (500 USDC)
 
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -268,7 +791,13 @@ fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
         &buy_msg,
         &coins(500_001_000, NATIVE_USDC),
     );
-    ensure!(res.is_err(), here("Purchase amount > Max purchase failure", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PurchaseAmountTooHigh { .. }))
+        ),
+        here(format!("Purchase amount > Max purchase failure: {:#?}", res), line!(), column!())
+    );
 
 
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -284,7 +813,13 @@ fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
         &buy_msg,
         &coins(100_000_900, NATIVE_USDC),
     );
-    ensure!(res.is_err(), here("Not rounded to 3rd decimal", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NotRoundedToThirdDecimal { .. }))
+        ),
+        here(format!("Not rounded to 3rd decimal: {:#?}", res), line!(), column!())
+    );
 
 
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -303,7 +838,13 @@ fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
         &buy_msg,
         &[usd, fkx],
     );
-    ensure!(res.is_err(), here("More than 1 coin sent", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::MultipleCoins))
+        ),
+        here(format!("More than 1 coin sent: {:#?}", res), line!(), column!())
+    );
 
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -317,7 +858,13 @@ fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
         &buy_msg,
         &[],
     );
-    ensure!(res.is_err(), here("More than 1 coin sent", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NoFunds))
+        ),
+        here(format!("More than 1 coin sent: {:#?}", res), line!(), column!())
+    );
 
 
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -332,11 +879,17 @@ fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
         &buy_msg,
         &coins(10, NATIVE_INVALID),
     );
-    ensure!(res.is_err(), here("Wrong denom", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::WrongDenom { .. }))
+        ),
+        here(format!("Wrong denom: {:#?}", res), line!(), column!())
+    );
 
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-    // CHECK: purchase doesn't go through if vest period > MAX_VEST
+    // CHECK: purchase doesn't go through if vest period > max_vest
     //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
     let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 53_u128 };
 
@@ -346,7 +899,13 @@ fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
         &buy_msg,
         &coins(10, NATIVE_USDC),
     );
-    ensure!(res.is_err(), here("Vest period > MAX_VEST", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::VestingPeriodTooLong { .. }))
+        ),
+        here(format!("Vest period > max_vest: {:#?}", res), line!(), column!())
+    );
 
 
 
@@ -380,24 +939,22 @@ fn test_purchasing_all_failures() -> Result<(), anyhow::Error> {
 // (X) - CHECK: user can't have more than 5 active purchases
 #[test]
 pub fn test_user_over_5_purchases() -> Result<(), anyhow::Error> {
-    use std::borrow::BorrowMut;
-    use crate::utils::*;
     use anyhow::Result;
     use cw_multi_test::AppResponse;
-    use std::ops::{Add, Sub};
+    use crate::error::ContractError;
 
     //~~~~~~~~~~~~~~~~~~~~
     // Setup
     //~~~~~~~~~~~~~~~~~~~~
     let mut router = App::default();
     // Users
-    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
     // Contract
     let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
 
     // contract has 1_000_000_000 JUNO (1,000 JUNO)
     // each user has 100_000_000 USDC (100 USDC)
-    // fake price is 5_000_000 (5 USDC per JUNO)
+    // oracle price is 5_000_000 (5 USDC per JUNO)
     // max_purchase is 500_000_000 (500 USDC)
 
     let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 };
@@ -420,7 +977,13 @@ pub fn test_user_over_5_purchases() -> Result<(), anyhow::Error> {
         &buy_msg,
         &coins(1_000_000, NATIVE_USDC),
     );
-    ensure!(res.is_err(), here("John's sixth buy should've failed", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::TooManyPositions { .. }))
+        ),
+        here(format!("John's sixth buy should've failed: {:#?}", res), line!(), column!())
+    );
 
     // Fast forward 10 weeks * 100_000 blocks per week (1_000_000 blocks)
     router.update_block(|current_blockinfo| {
@@ -439,10 +1002,9 @@ pub fn test_user_over_5_purchases() -> Result<(), anyhow::Error> {
     );
     ensure!(res.is_ok(), here("Vest a purchase", line!(), column!()));
 
-    let q = crate::msg::QueryMsg::GetUserPurchaseIds { user_wallet: john.address.to_string() };
+    let q = crate::msg::QueryMsg::GetUserPurchaseIds { user_wallet: john.address.to_string(), start_after: None, limit: None };
     let qrez: crate::query::UserPurchaseIds = {
-        let qres: Binary = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap();
-        cosmwasm_std::from_binary(&qres).unwrap()
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
     };
 
     ensure!((qrez.uuids.len() == 4), here(format!("Johns active vests: {:#?}", qrez.uuids), line!(), column!()));
@@ -493,23 +1055,22 @@ pub fn test_user_over_5_purchases() -> Result<(), anyhow::Error> {
 #[test]
 pub fn test_purchase_over_contract_balance() -> Result<(), anyhow::Error> {
     use std::borrow::BorrowMut;
-    use crate::utils::*;
     use anyhow::Result;
     use cw_multi_test::AppResponse;
-    use std::ops::{Add, Sub};
+    use crate::error::ContractError;
 
     //~~~~~~~~~~~~~~~~~~~~
     // Setup
     //~~~~~~~~~~~~~~~~~~~~
     let mut router = App::default();
     // Users
-    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
     // Contract
     let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
 
     // contract has 1_000_000_000 JUNO (1,000 JUNO)
     // each user has 100_000_000 USDC (100 USDC)
-    // fake price is 5_000_000 (5 USDC per JUNO)
+    // oracle price is 5_000_000 (5 USDC per JUNO)
     // max_purchase is 500_000_000 (500 USDC)
 
     // Give whale 10_000_000_000 USDC (10,000 USDC)
@@ -544,7 +1105,13 @@ pub fn test_purchase_over_contract_balance() -> Result<(), anyhow::Error> {
         &buy_msg,
         &coins(1_000_000, NATIVE_USDC),
     );
-    ensure!(res.is_err(), here("John's sixth buy should've failed", line!(), column!()));
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::TooManyPositions { .. }))
+        ),
+        here(format!("John's sixth buy should've failed: {:#?}", res), line!(), column!())
+    );
 
     // Fast forward 10 weeks * 100_000 blocks per week (1_000_000 blocks)
     router.update_block(|current_blockinfo| {
@@ -563,10 +1130,9 @@ pub fn test_purchase_over_contract_balance() -> Result<(), anyhow::Error> {
     );
     ensure!(res.is_ok(), here("Vest a purchase", line!(), column!()));
 
-    let q = crate::msg::QueryMsg::GetUserPurchaseIds { user_wallet: john.address.to_string() };
+    let q = crate::msg::QueryMsg::GetUserPurchaseIds { user_wallet: john.address.to_string(), start_after: None, limit: None };
     let qrez: crate::query::UserPurchaseIds = {
-        let qres: Binary = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap();
-        cosmwasm_std::from_binary(&qres).unwrap()
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
     };
 
     ensure!((qrez.uuids.len() == 4), here(format!("Johns active vests: {:#?}", qrez.uuids), line!(), column!()));
@@ -580,3 +1146,3322 @@ pub fn test_purchase_over_contract_balance() -> Result<(), anyhow::Error> {
 
 // Check that vesting claims give correct amount
 
+
+
+// (X) - CHECK: ClaimAll pays out every claimable position in one transfer
+// (X) - CHECK: ClaimAll skips positions with nothing claimable
+// (X) - CHECK: ClaimAll respects limit
+#[test]
+pub fn test_claim_all() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    // uuid 1: 10 weeks | 1_999_600 * 1.10 = 2_199_560 | 2 per block
+    // uuid 2: 1 week   | 1_999_600 * 1.01 = 2_019_596
+    for vesting_period in [10_u128, 1_u128] {
+        let res: Result<AppResponse> = router.execute_contract(
+            john.address.clone(),
+            cpbond_contract.clone(),
+            &crate::msg::ExecuteMsg::Purchase { vesting_period },
+            &coins(10_000_000, NATIVE_USDC),
+        );
+        ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+    }
+
+    // Fast forward 2 weeks
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 200_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(1_200_000);
+    });
+
+    // uuid 3: bought this block, nothing claimable yet
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let claim_msg = crate::msg::ExecuteMsg::ClaimAll { limit: None };
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &claim_msg,
+        &[]
+    );
+    ensure!(res.is_ok(), here(format!("john claim all {:#?}", res), line!(), column!()));
+
+    // 400_000 from uuid 1 + 2_019_596 from uuid 2
+    let john_juno: Coin = router.wrap().query_balance(john.address.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (john_juno.amount == Uint128::from(2_419_596_u128)),
+        here(format!("john juno after claim all: {}", john_juno.amount), line!(), column!())
+    );
+
+    // uuid 2 fully vested and removed
+    let q = crate::msg::QueryMsg::GetUserPurchaseIds { user_wallet: john.address.to_string(), start_after: None, limit: None };
+    let qrez: crate::query::UserPurchaseIds = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+    ensure!((qrez.uuids == vec![1, 3]), here(format!("Johns active vests: {:#?}", qrez.uuids), line!(), column!()));
+
+    // Nothing left to claim this block
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &claim_msg,
+        &[]
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NothingToClaim))
+        ),
+        here(format!("Claim all with nothing claimable should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Next block, limit 1 only claims uuid 1
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 1;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(6);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ClaimAll { limit: Some(1) },
+        &[]
+    );
+    ensure!(res.is_ok(), here(format!("john claim all limit 1 {:#?}", res), line!(), column!()));
+
+    let john_juno: Coin = router.wrap().query_balance(john.address.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (john_juno.amount == Uint128::from(2_419_598_u128)),
+        here(format!("john juno after limited claim all: {}", john_juno.amount), line!(), column!())
+    );
+
+    let q = crate::msg::QueryMsg::GetUserPurchaseInfos { user_wallet: john.address.to_string(), start_after: None, limit: None };
+    let qrez: crate::query::UserPurchaseInfos = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+    ensure!(
+        (qrez.purchase_infos[1].1.already_claimed.is_zero()),
+        here(format!("uuid 3 should be untouched: {:#?}", qrez.purchase_infos), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: purchase uses the price reported by the oracle
+// (X) - CHECK: purchase fails if the oracle reports a zero price
+#[test]
+pub fn test_purchase_uses_oracle_price() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract, oracle) = setup_contract::setup_with_oracle(router, &admin.address);
+
+    // Price moves to 2 USDC per JUNO
+    // 10 USDC / 2.001 = 4_997_501 * 1.1 = 5_497_251
+    mock_oracle::set_price(router, &admin.address, &oracle, 2_000_000);
+
+    let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 };
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let q = crate::msg::QueryMsg::GetUserPurchaseInfos { user_wallet: john.address.to_string(), start_after: None, limit: None };
+    let qrez: crate::query::UserPurchaseInfos = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+    ensure!(
+        (qrez.purchase_infos[0].1.amount_purchased == Uint128::from(5_497_251_u128)),
+        here(format!("amount purchased: {}", qrez.purchase_infos[0].1.amount_purchased), line!(), column!())
+    );
+
+    // A zero price is rejected
+    mock_oracle::set_price(router, &admin.address, &oracle, 0);
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::ZeroPrice { .. }))
+        ),
+        here(format!("Purchase with zero oracle price should've failed: {:#?}", res), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: purchase fails with a stale price
+// (X) - CHECK: purchase fails when price deviates too far from the last accepted price
+// (X) - CHECK: consecutive breaches through CheckPrice set PAUSED
+// (X) - CHECK: an accepted price resets the breach count
+// (X) - CHECK: an anchor older than max_price_age fails closed
+// (X) - CHECK: resuming keeps the anchor, only admin re-anchors with ResetPriceAnchor
+#[test]
+pub fn test_price_guards() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::error::ContractError;
+    use crate::query::LastPriceResponse;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract | 1 hour max age, 20% max deviation, pause after 2 breaches
+    let (router, cpbond_contract, oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.price_guards.max_price_age = 3_600;
+            msg.price_guards.max_deviation = cosmwasm_std::Decimal::percent(20);
+            msg.price_guards.max_breaches = 2;
+        });
+
+    let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 };
+    let last_price_q = crate::msg::QueryMsg::GetLastPrice {};
+
+    // First purchase sets last accepted price
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let qrez: LastPriceResponse = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &last_price_q).unwrap()
+    };
+    ensure!(
+        (qrez.last_price.map(|l| l.price) == Some(Uint128::from(5_000_000_u128))),
+        here("last price should be 5_000_000", line!(), column!())
+    );
+
+    // Price jumps 50%, purchase rejected
+    mock_oracle::set_price(router, &admin.address, &oracle, 7_500_000);
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PriceDeviation { .. }))
+        ),
+        here(format!("Deviation should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Within 20%, purchase goes through
+    mock_oracle::set_price(router, &admin.address, &oracle, 5_500_000);
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    // Oracle stops updating for over an hour
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 1_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(3_601);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::StalePrice { age: 3_601, max_age: 3_600 }))
+        ),
+        here(format!("Stale price should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Stale price is a breach, recorded but not paused yet
+    let check_msg = crate::msg::ExecuteMsg::CheckPrice {};
+    let res: Result<AppResponse> =
+        router.execute_contract(john.address.clone(), cpbond_contract.clone(), &check_msg, &[]);
+    ensure!(res.is_ok(), here(format!("check price {:#?}", res), line!(), column!()));
+
+    let qrez: LastPriceResponse = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &last_price_q).unwrap()
+    };
+    ensure!((qrez.consecutive_breaches == 1), here(format!("{:#?}", qrez), line!(), column!()));
+
+    // Fresh price, but the anchor is now over an hour old and doesn't bound it anymore
+    mock_oracle::set_price(router, &admin.address, &oracle, 5_500_000);
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::StaleAnchor { .. }))
+        ),
+        here(format!("Stale anchor should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Only admin re-anchors, which resets the count
+    let reset_msg = crate::msg::ExecuteMsg::ResetPriceAnchor {};
+    let res: Result<AppResponse> =
+        router.execute_contract(john.address.clone(), cpbond_contract.clone(), &reset_msg, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin reset should've failed: {:#?}", res), line!(), column!())
+    );
+    router.execute_contract(admin.address.clone(), cpbond_contract.clone(), &reset_msg, &[])?;
+
+    let qrez: LastPriceResponse = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &last_price_q).unwrap()
+    };
+    ensure!(
+        (qrez.last_price.as_ref().map(|l| l.price) == Some(Uint128::from(5_500_000_u128)) && qrez.consecutive_breaches == 0),
+        here(format!("anchor after reset: {:#?}", qrez), line!(), column!())
+    );
+
+    // Two breaches in a row pause purchasing
+    mock_oracle::set_price(router, &admin.address, &oracle, 1_000_000);
+    router.execute_contract(john.address.clone(), cpbond_contract.clone(), &check_msg, &[])?;
+    router.execute_contract(john.address.clone(), cpbond_contract.clone(), &check_msg, &[])?;
+
+    mock_oracle::set_price(router, &admin.address, &oracle, 5_500_000);
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PurchasingPaused))
+        ),
+        here(format!("Purchase should've failed while paused: {:#?}", res), line!(), column!())
+    );
+
+    // Resuming keeps the anchor, the manipulated price is still rejected
+    mock_oracle::set_price(router, &admin.address, &oracle, 1_000_000);
+    router.execute_contract(admin.address.clone(), cpbond_contract.clone(), &ExecuteMsg::ResumePurchasing {}, &[])?;
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PriceDeviation { .. }))
+        ),
+        here(format!("Deviation after resume should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // The market really moved to 1_000_000, admin re-anchors explicitly
+    router.execute_contract(admin.address.clone(), cpbond_contract.clone(), &reset_msg, &[])?;
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("buy after reset {:#?}", res), line!(), column!()));
+
+    let qrez: LastPriceResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &last_price_q)?;
+    ensure!(
+        (qrez.last_price.as_ref().map(|l| l.price) == Some(Uint128::from(1_000_000_u128)) && qrez.consecutive_breaches == 0),
+        here(format!("anchor after reset: {:#?}", qrez), line!(), column!())
+    );
+
+    // Guards can be changed without sudo
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::UpdateConfig {
+            price_guards: Some(crate::state::PriceGuards {
+                max_price_age: 7_200,
+                max_deviation: cosmwasm_std::Decimal::percent(50),
+                max_breaches: 3,
+            }),
+            limits: None,
+            discount_curve: None,
+            proceeds_recipients: None,
+        },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("update price guards {:#?}", res), line!(), column!()));
+
+    let config: crate::query::ConfigResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetConfig {})?;
+    ensure!(
+        (config.config.price_guards.max_breaches == 3),
+        here(format!("price guards: {:#?}", config.config.price_guards), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: Median price source uses the median of its oracles
+// (X) - CHECK: purchase fails when fewer than min_responses oracles answer
+// (X) - CHECK: only admin can add / remove price sources
+// (X) - CHECK: can't remove sources below min_responses
+#[test]
+pub fn test_median_price_source() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::error::ContractError;
+    use crate::query::LastPriceResponse;
+    use crate::state::PriceSource;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Extra oracles, one of them compromised
+    let oracle_b = mock_oracle::init_oracle(router, &admin.address, 5_200_000);
+    let oracle_c = mock_oracle::init_oracle(router, &admin.address, 100_000_000);
+    let oracle_d = mock_oracle::init_oracle(router, &admin.address, 5_100_000);
+    // Contract
+    let (router, cpbond_contract, oracle_a) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            let PriceSource::Oracle { contract } = msg.price_source.clone() else {
+                unreachable!()
+            };
+            msg.price_source = PriceSource::Median {
+                sources: vec![contract, oracle_b.clone(), oracle_c.clone()],
+                min_responses: 2,
+            };
+        });
+
+    let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 };
+    let last_price_q = crate::msg::QueryMsg::GetLastPrice {};
+
+    // Median of 5.0, 5.2, 100 is 5.2
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let qrez: LastPriceResponse = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &last_price_q).unwrap()
+    };
+    ensure!(
+        (qrez.last_price.map(|l| l.price) == Some(Uint128::from(5_200_000_u128))),
+        here("last price should be 5_200_000", line!(), column!())
+    );
+
+    // Only oracle_c gives a valid price
+    mock_oracle::set_price(router, &admin.address, &oracle_a, 0);
+    mock_oracle::set_price(router, &admin.address, &oracle_b, 0);
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NotEnoughPriceSources { responded: 1, required: 2 }))
+        ),
+        here(format!("Not enough sources should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Non admin can't change sources
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::AddPriceSource { contract: oracle_d.to_string() },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin add source should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Admin swaps oracle_c for oracle_d
+    router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::RemovePriceSource { contract: oracle_c.to_string() },
+        &[],
+    )?;
+    router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::AddPriceSource { contract: oracle_d.to_string() },
+        &[],
+    )?;
+
+    // Median of 5.0 and 5.1 is 5.05 (oracle_b still 0)
+    mock_oracle::set_price(router, &admin.address, &oracle_a, 5_000_000);
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let qrez: LastPriceResponse = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &last_price_q).unwrap()
+    };
+    ensure!(
+        (qrez.last_price.map(|l| l.price) == Some(Uint128::from(5_050_000_u128))),
+        here("last price should be 5_050_000", line!(), column!())
+    );
+
+    // Sources can't drop below min_responses
+    router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::RemovePriceSource { contract: oracle_a.to_string() },
+        &[],
+    )?;
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::RemovePriceSource { contract: oracle_b.to_string() },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidPriceSource { .. }))
+        ),
+        here(format!("Remove below min_responses should've failed: {:#?}", res), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: purchase fails without enough TWAP history
+// (X) - CHECK: TWAP price is the time weighted average over the window
+// (X) - CHECK: snapshots older than the window are pruned
+#[test]
+pub fn test_twap_price_source() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::query::LastPriceResponse;
+    use crate::state::PriceSource;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Pair starts at 5 USDC per JUNO
+    let pair = mock_pair::init_pair(router, &admin.address, 5_000_000);
+    // Contract | 1 hour TWAP
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.price_source = PriceSource::Twap { pair: pair.clone(), window: 3_600 };
+        });
+
+    let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 };
+    let snapshot_msg = crate::msg::ExecuteMsg::RecordTwapSnapshot {};
+    let last_price_q = crate::msg::QueryMsg::GetLastPrice {};
+
+    // No snapshots yet
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NotEnoughTwapHistory { .. }))
+        ),
+        here(format!("Purchase without TWAP history should've failed: {:#?}", res), line!(), column!())
+    );
+
+    router.execute_contract(john.address.clone(), cpbond_contract.clone(), &snapshot_msg, &[])?;
+
+    // 30 minutes at 5, then price moves to 6
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 300;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(1_800);
+    });
+    mock_pair::set_price(router, &admin.address, &pair, 6_000_000);
+    router.execute_contract(john.address.clone(), cpbond_contract.clone(), &snapshot_msg, &[])?;
+
+    // Window not covered yet
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NotEnoughTwapHistory { .. }))
+        ),
+        here(format!("Purchase with short TWAP history should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // 30 minutes at 6, TWAP = 5.5
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 300;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(1_800);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let qrez: LastPriceResponse = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &last_price_q).unwrap()
+    };
+    ensure!(
+        (qrez.last_price.map(|l| l.price) == Some(Uint128::from(5_500_000_u128))),
+        here("last price should be 5_500_000", line!(), column!())
+    );
+
+    // Only the snapshot at the start of the window and newer are kept
+    let snapshots = router.wrap().query_wasm_raw(
+        cpbond_contract.clone(),
+        crate::state::TWAP_SNAPSHOTS.key(router.block_info().time.seconds() - 3_600).to_vec(),
+    )?;
+    ensure!(snapshots.is_some(), here("Window start snapshot should be kept", line!(), column!()));
+
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 300;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(1_800);
+    });
+    router.execute_contract(john.address.clone(), cpbond_contract.clone(), &snapshot_msg, &[])?;
+
+    let snapshots = router.wrap().query_wasm_raw(
+        cpbond_contract.clone(),
+        crate::state::TWAP_SNAPSHOTS.key(router.block_info().time.seconds() - 5_400).to_vec(),
+    )?;
+    ensure!(snapshots.is_none(), here("Old snapshot should be pruned", line!(), column!()));
+
+    // Switching to a pair that reports no movement, old pair's snapshots are dropped
+    let flat_pair = mock_pair::init_pair(router, &admin.address, 0);
+    router.wasm_sudo(cpbond_contract.clone(), &crate::msg::SudoMsg::OverrideConfig {
+        price_source: Some(PriceSource::Twap { pair: flat_pair.clone(), window: 3_600 }),
+        price_guards: None,
+        limits: None,
+        discount_curve: None,
+        proceeds_recipients: None,
+        purchase_fee: None,
+        timelock_blocks: None,
+    })?;
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NotEnoughTwapHistory { .. }))
+        ),
+        here(format!("Old pair snapshots should've been cleared: {:#?}", res), line!(), column!())
+    );
+
+    router.execute_contract(john.address.clone(), cpbond_contract.clone(), &snapshot_msg, &[])?;
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 600;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(3_600);
+    });
+
+    // TWAP of 0 is rejected
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &buy_msg,
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::ZeroPrice { .. }))
+        ),
+        here(format!("Zero TWAP should've failed: {:#?}", res), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: only admin can UpdateConfig
+// (X) - CHECK: UpdateConfig rejects invalid limits
+// (X) - CHECK: updated limits apply to the next purchase
+#[test]
+pub fn test_update_limits() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::query::ConfigResponse;
+    use crate::state::SaleLimits;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    let new_limits = SaleLimits {
+        min_purchase_amount: Uint128::from(2_000_000_u128),
+        max_purchase_amount: Uint128::from(50_000_000_u128),
+        min_vest: 4,
+        max_vest: 12,
+        blocks_in_week: 100_000,
+        max_positions: 2,
+    };
+
+    // Non admin can't update
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig { price_guards: None, limits: Some(new_limits.clone()), discount_curve: None, proceeds_recipients: None },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin update config should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // min greater than max is rejected
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig {
+            price_guards: None,
+            limits: Some(SaleLimits { min_vest: 13, ..new_limits.clone() }),
+            discount_curve: None,
+            proceeds_recipients: None,
+        },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidLimits { .. }))
+        ),
+        here(format!("min_vest > max_vest should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig { price_guards: None, limits: Some(new_limits.clone()), discount_curve: None, proceeds_recipients: None },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("admin update config {:#?}", res), line!(), column!()));
+
+    let qrez: ConfigResponse = {
+        router
+            .wrap()
+            .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetConfig {})
+            .unwrap()
+    };
+    ensure!((qrez.config.limits == new_limits), here(format!("{:#?}", qrez.config), line!(), column!()));
+
+    // Below min purchase amount
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PurchaseAmountTooLow { .. }))
+        ),
+        here(format!("Below min purchase should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Below min vest
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 3_u128 },
+        &coins(2_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::VestingPeriodTooShort { .. }))
+        ),
+        here(format!("Below min vest should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Above new max vest
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 13_u128 },
+        &coins(2_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::VestingPeriodTooLong { .. }))
+        ),
+        here(format!("Above max vest should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Two positions allowed, third fails | large enough to vest at least 1 per block
+    for x in 0..2 {
+        let res: Result<AppResponse> = router.execute_contract(
+            john.address.clone(),
+            cpbond_contract.clone(),
+            &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+            &coins(20_000_000, NATIVE_USDC),
+        );
+        ensure!(res.is_ok(), here(format!("john buy {} {:#?}", x, res), line!(), column!()));
+    }
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(2_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::TooManyPositions { .. }))
+        ),
+        here(format!("Third position should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Shorter weeks only apply to new purchases, open positions keep the pace they were bought at
+    let before: crate::query::ClaimableResponse = router.wrap().query_wasm_smart(
+        cpbond_contract.clone(),
+        &crate::msg::QueryMsg::GetClaimable { user: john.address.to_string(), uuid: 1 },
+    )?;
+
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig {
+            price_guards: None,
+            limits: Some(SaleLimits { blocks_in_week: 10_000, ..new_limits.clone() }),
+            discount_curve: None,
+            proceeds_recipients: None,
+        },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("admin update blocks_in_week {:#?}", res), line!(), column!()));
+
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 500_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(3_000_000);
+    });
+
+    let after: crate::query::ClaimableResponse = router.wrap().query_wasm_smart(
+        cpbond_contract.clone(),
+        &crate::msg::QueryMsg::GetClaimable { user: john.address.to_string(), uuid: 1 },
+    )?;
+    ensure!(
+        (!before.vest_per_block.is_zero()
+            && after.vest_per_block == before.vest_per_block
+            && after.vest_expiration == before.vest_expiration
+            && after.claimable == before.vest_per_block * Uint128::from(500_000_u128)),
+        here(format!("before: {:#?} | after: {:#?}", before, after), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ClaimAvailable { uuid: 1 },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("claim after blocks_in_week change {:#?}", res), line!(), column!()));
+
+    Ok(())
+}
+
+
+// (X) - CHECK: tiered curve bonus lookup
+// (X) - CHECK: purchase uses the configured curve
+// (X) - CHECK: invalid curves rejected on update
+// (X) - CHECK: concave curve is capped
+// (X) - CHECK: GetBonus rejects periods outside min_vest / max_vest
+#[test]
+pub fn test_discount_curves() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use cosmwasm_std::Decimal;
+    use crate::query::BonusResponse;
+    use crate::state::{DiscountCurve, DiscountTier};
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract | 4w -> 3%, 12w -> 12%, 52w -> 40%
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.discount_curve = DiscountCurve::Tiered {
+                tiers: vec![
+                    DiscountTier { min_weeks: 4, bonus: Decimal::percent(3) },
+                    DiscountTier { min_weeks: 12, bonus: Decimal::percent(12) },
+                    DiscountTier { min_weeks: 52, bonus: Decimal::percent(40) },
+                ],
+            };
+        });
+
+    let bonus_for = |router: &App, vesting_period: u128| -> Decimal {
+        let q = crate::msg::QueryMsg::GetBonus { vesting_period };
+        let res: BonusResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap();
+        res.bonus
+    };
+
+    for (weeks, percent) in [(2, 0), (4, 3), (11, 3), (12, 12), (51, 12), (52, 40)] {
+        ensure!(
+            (bonus_for(router, weeks) == Decimal::percent(percent)),
+            here(format!("{} weeks: {}", weeks, bonus_for(router, weeks)), line!(), column!())
+        );
+    }
+
+    // Purchase would reject 53 weeks, so the bonus isn't quoted either
+    let res: cosmwasm_std::StdResult<BonusResponse> = router.wrap()
+        .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetBonus { vesting_period: 53 });
+    ensure!(
+        res.as_ref().is_err_and(|e| e.to_string().contains("Vesting period too long")),
+        here(format!("Bonus past max_vest should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // 10 USDC / 5.001 = 1_999_600 * 1.12 = 2_239_552
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 12_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let q = crate::msg::QueryMsg::GetUserPurchaseInfos { user_wallet: john.address.to_string(), start_after: None, limit: None };
+    let qrez: crate::query::UserPurchaseInfos = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+    ensure!(
+        (qrez.purchase_infos[0].1.amount_purchased == Uint128::from(2_239_552_u128)),
+        here(format!("amount purchased: {}", qrez.purchase_infos[0].1.amount_purchased), line!(), column!())
+    );
+
+    // Tiers out of order are rejected
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig {
+            price_guards: None,
+            limits: None,
+            discount_curve: Some(DiscountCurve::Tiered {
+                tiers: vec![
+                    DiscountTier { min_weeks: 12, bonus: Decimal::percent(12) },
+                    DiscountTier { min_weeks: 4, bonus: Decimal::percent(3) },
+                ],
+            }),
+            proceeds_recipients: None,
+        },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidDiscountCurve { .. }))
+        ),
+        here(format!("Unordered tiers should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Concave | 5% * sqrt(weeks), capped at 25%
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig {
+            price_guards: None,
+            limits: None,
+            discount_curve: Some(DiscountCurve::Concave {
+                scale: Decimal::percent(5),
+                cap: Decimal::percent(25),
+            }),
+            proceeds_recipients: None,
+        },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("concave update {:#?}", res), line!(), column!()));
+
+    ensure!((bonus_for(router, 4) == Decimal::percent(10)), here("4 weeks concave", line!(), column!()));
+    ensure!((bonus_for(router, 16) == Decimal::percent(20)), here("16 weeks concave", line!(), column!()));
+    ensure!((bonus_for(router, 52) == Decimal::percent(25)), here("52 weeks concave", line!(), column!()));
+
+    // No curve may give more than a 100% bonus
+    for curve in [
+        DiscountCurve::Linear { slope: Decimal::percent(2) },
+        DiscountCurve::Tiered { tiers: vec![DiscountTier { min_weeks: 4, bonus: Decimal::percent(150) }] },
+        DiscountCurve::Concave { scale: Decimal::percent(50), cap: Decimal::percent(200) },
+    ] {
+        let res: Result<AppResponse> = router.execute_contract(
+            admin.address.clone(),
+            cpbond_contract.clone(),
+            &crate::msg::ExecuteMsg::UpdateConfig {
+                price_guards: None,
+                limits: None,
+                discount_curve: Some(curve.clone()),
+                proceeds_recipients: None,
+            },
+            &[],
+        );
+        ensure!(
+            matches!(
+                res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+                Err(Some(ContractError::InvalidDiscountCurve { .. }))
+            ),
+            here(format!("{:?} should've failed: {:#?}", curve, res), line!(), column!())
+        );
+    }
+
+    Ok(())
+}
+
+
+// (X) - CHECK: DebtRatio bonus shrinks as bond debt grows relative to inventory
+// (X) - CHECK: DebtRatio bonus recovers as debt decays
+#[test]
+pub fn test_debt_ratio_curve() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use cosmwasm_std::Decimal;
+    use crate::query::{BonusResponse, DebtRatioResponse};
+    use crate::state::DiscountCurve;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract | 1% per week, BCV 10, debt decays over 1_000_000 blocks
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.discount_curve = DiscountCurve::DebtRatio {
+                slope: Decimal::percent(1),
+                control_variable: Decimal::from_atomics(10_u128, 0).unwrap(),
+                decay_blocks: 1_000_000,
+            };
+        });
+
+    let bonus_for = |router: &App, vesting_period: u128| -> Decimal {
+        let q = crate::msg::QueryMsg::GetBonus { vesting_period };
+        let res: BonusResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap();
+        res.bonus
+    };
+
+    // No debt, full bonus
+    ensure!((bonus_for(router, 10) == Decimal::percent(10)), here("no debt bonus", line!(), column!()));
+
+    // 100 USDC / 5.001 = 19_996_000 * 1.1 = 21_995_600
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(100_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let qrez: DebtRatioResponse = {
+        router
+            .wrap()
+            .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetDebtRatio {})
+            .unwrap()
+    };
+    ensure!(
+        (qrez.debt == Uint128::from(21_995_600_u128)
+            && qrez.inventory == Uint128::from(978_004_400_u128)),
+        here(format!("{:#?}", qrez), line!(), column!())
+    );
+
+    // debt ratio ~0.0225, bonus ~10% * (1 - 0.225)
+    let after_purchase = bonus_for(router, 10);
+    ensure!(
+        (after_purchase > Decimal::percent(7) && after_purchase < Decimal::percent(8)),
+        here(format!("bonus after purchase: {}", after_purchase), line!(), column!())
+    );
+
+    // Half the debt decayed
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 500_000;
+    });
+    let half_decayed = bonus_for(router, 10);
+    ensure!(
+        (half_decayed > after_purchase && half_decayed < Decimal::percent(10)),
+        here(format!("bonus after half decay: {}", half_decayed), line!(), column!())
+    );
+
+    // Fully decayed, full bonus again
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 500_000;
+    });
+    ensure!((bonus_for(router, 10) == Decimal::percent(10)), here("decayed bonus", line!(), column!()));
+
+    Ok(())
+}
+
+
+// (X) - CHECK: cw20 sale asset deposited through Receive
+// (X) - CHECK: native AddSaleFunds and wrong cw20 rejected for cw20 sale asset
+// (X) - CHECK: claims pay out cw20 sale asset
+#[test]
+pub fn test_cw20_sale_asset() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use cw20::Cw20ExecuteMsg;
+    use crate::state::AssetInfo;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Sale token, and a second token that isn't for sale
+    let cw20_juno = setup_cw20::init_cw20(router, &admin.address, "CWJUNO", vec![(&admin.address, 1_000_000_000)]);
+    let cw20_fake = setup_cw20::init_cw20(router, &admin.address, "CWFAKE", vec![(&admin.address, 1_000_000_000)]);
+    // Contract, setup_custom funds native JUNO which isn't the sale asset here
+    let oracle = mock_oracle::init_oracle(router, &admin.address, mock_oracle::INITIAL_PRICE);
+    let mut msg = setup_contract::default_instantiate_msg(&oracle);
+    msg.beingsold_asset = AssetInfo::Cw20 { contract: cw20_juno.clone() };
+    let cpbond_contract = setup_contract::init_cpbond(router, &admin.address, &msg);
+
+    let add_funds = |amount: u128| Cw20ExecuteMsg::Send {
+        contract: cpbond_contract.to_string(),
+        amount: Uint128::from(amount),
+        msg: cosmwasm_std::to_json_binary(&crate::msg::ReceiveMsg::AddSaleFunds {}).unwrap(),
+    };
+
+    // Wrong cw20 rejected
+    let res: Result<AppResponse> =
+        router.execute_contract(admin.address.clone(), cw20_fake.clone(), &add_funds(1_000_000), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::WrongDenom { .. }))
+        ),
+        here(format!("Wrong cw20 sale funds should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Native sale funds rejected
+    router.init_modules(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &admin.address, coins(1_000_000, NATIVE_JUNO))
+            .unwrap()
+    });
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::AddSaleFunds {},
+        &coins(1_000_000, NATIVE_JUNO),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::UseCw20Send { .. }))
+        ),
+        here(format!("Native sale funds should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res: Result<AppResponse> =
+        router.execute_contract(admin.address.clone(), cw20_juno.clone(), &add_funds(1_000_000_000), &[]);
+    ensure!(res.is_ok(), here(format!("add cw20 sale funds {:#?}", res), line!(), column!()));
+
+    router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ResumePurchasing {},
+        &[],
+    )?;
+
+    // 10 USDC / 5.001 = 1_999_600 * 1.1 = 2_199_560
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 1_000_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(6_000_000);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ClaimAvailable { uuid: 1 },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("john claim {:#?}", res), line!(), column!()));
+
+    let john_cw20 = setup_cw20::cw20_balance(router, &cw20_juno, &john.address);
+    ensure!(
+        (john_cw20 == Uint128::from(2_199_560_u128)),
+        here(format!("john cw20 juno: {}", john_cw20), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: cw20 cost asset purchase through Receive
+// (X) - CHECK: cw20 and native buyers get identical positions
+// (X) - CHECK: cw20 purchase fails with wrong token | over max | not rounded | native funds
+#[test]
+pub fn test_cw20_cost_asset() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use cw20::Cw20ExecuteMsg;
+    use crate::state::AssetInfo;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // cw20 stablecoin, and a second token that isn't accepted
+    let cw20_usdc = setup_cw20::init_cw20(
+        router,
+        &admin.address,
+        "CWUSDC",
+        vec![(&john.address, 1_000_000_000)],
+    );
+    let cw20_fake = setup_cw20::init_cw20(
+        router,
+        &admin.address,
+        "CWFAKE",
+        vec![(&john.address, 1_000_000_000)],
+    );
+    // Contract
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.cost_asset = AssetInfo::Cw20 { contract: cw20_usdc.clone() };
+        });
+
+    let buy = |amount: u128| Cw20ExecuteMsg::Send {
+        contract: cpbond_contract.to_string(),
+        amount: Uint128::from(amount),
+        msg: cosmwasm_std::to_json_binary(&crate::msg::ReceiveMsg::Purchase { vesting_period: 10 }).unwrap(),
+    };
+
+    // Wrong token
+    let res: Result<AppResponse> =
+        router.execute_contract(john.address.clone(), cw20_fake.clone(), &buy(10_000_000), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::WrongDenom { .. }))
+        ),
+        here(format!("Wrong cw20 should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Over max purchase amount
+    let res: Result<AppResponse> =
+        router.execute_contract(john.address.clone(), cw20_usdc.clone(), &buy(500_001_000), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PurchaseAmountTooHigh { .. }))
+        ),
+        here(format!("Over max should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Not rounded to 3rd decimal
+    let res: Result<AppResponse> =
+        router.execute_contract(john.address.clone(), cw20_usdc.clone(), &buy(10_000_900), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NotRoundedToThirdDecimal { .. }))
+        ),
+        here(format!("Not rounded should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Native funds rejected when cost asset is cw20
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::UseCw20Send { .. }))
+        ),
+        here(format!("Native purchase should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Same purchase as the native path in test_purchase
+    let res: Result<AppResponse> =
+        router.execute_contract(john.address.clone(), cw20_usdc.clone(), &buy(10_000_000), &[]);
+    ensure!(res.is_ok(), here(format!("john cw20 buy {:#?}", res), line!(), column!()));
+
+    let q = crate::msg::QueryMsg::GetUserPurchaseInfos { user_wallet: john.address.to_string(), start_after: None, limit: None };
+    let qrez: crate::query::UserPurchaseInfos = {
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+    ensure!(
+        (qrez.purchase_infos[0].1.amount_purchased == Uint128::from(2_199_560_u128)),
+        here(format!("amount purchased: {}", qrez.purchase_infos[0].1.amount_purchased), line!(), column!())
+    );
+
+    let contract_usdc = setup_cw20::cw20_balance(router, &cw20_usdc, &cpbond_contract);
+    ensure!(
+        (contract_usdc == Uint128::from(10_000_000_u128)),
+        here(format!("contract cw20 usdc: {}", contract_usdc), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: only admin can withdraw
+// (X) - CHECK: inventory withdrawals limited to BALANCE
+// (X) - CHECK: proceeds withdrawals never touch sale tokens owed to positions
+#[test]
+pub fn test_admin_withdrawals() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::state::AssetInfo;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    let usdc = AssetInfo::Native { denom: NATIVE_USDC.to_string() };
+    let juno = AssetInfo::Native { denom: NATIVE_JUNO.to_string() };
+
+    // 10 USDC / 5.001 = 1_999_600 * 1.1 = 2_199_560 owed to john
+    // BALANCE = 1_000_000_000 - 2_199_560 = 997_800_440
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    // Non admin
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::WithdrawInventory { amount: Uint128::from(1_u128) },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin withdraw inventory should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::WithdrawProceeds {
+            asset: usdc.clone(),
+            amount: Uint128::from(1_u128),
+            recipient: john.address.to_string(),
+        },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin withdraw proceeds should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // More than BALANCE
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::WithdrawInventory { amount: Uint128::from(997_800_441_u128) },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InsufficientInventory { .. }))
+        ),
+        here(format!("Withdraw over BALANCE should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::WithdrawInventory { amount: Uint128::from(997_800_440_u128) },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("withdraw inventory {:#?}", res), line!(), column!()));
+
+    let admin_juno: Coin = router.wrap().query_balance(admin.address.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (admin_juno.amount == Uint128::from(997_800_440_u128)),
+        here(format!("admin juno: {}", admin_juno.amount), line!(), column!())
+    );
+
+    // Everything left in JUNO is owed to john
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::WithdrawProceeds {
+            asset: juno.clone(),
+            amount: Uint128::from(1_u128),
+            recipient: admin.address.to_string(),
+        },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InsufficientProceeds { .. }))
+        ),
+        here(format!("Withdraw owed sale tokens should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Purchase proceeds
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::WithdrawProceeds {
+            asset: usdc.clone(),
+            amount: Uint128::from(10_000_001_u128),
+            recipient: sam.address.to_string(),
+        },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InsufficientProceeds { .. }))
+        ),
+        here(format!("Withdraw over proceeds should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::WithdrawProceeds {
+            asset: usdc,
+            amount: Uint128::from(10_000_000_u128),
+            recipient: sam.address.to_string(),
+        },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("withdraw proceeds {:#?}", res), line!(), column!()));
+
+    let sam_usdc: Coin = router.wrap().query_balance(sam.address.to_string(), NATIVE_USDC).unwrap();
+    ensure!(
+        (sam_usdc.amount == Uint128::from(110_000_000_u128)),
+        here(format!("sam usdc: {}", sam_usdc.amount), line!(), column!())
+    );
+
+    // john can still fully claim
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 1_000_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(6_000_000);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ClaimAvailable { uuid: 1 },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("john claim {:#?}", res), line!(), column!()));
+
+    Ok(())
+}
+
+
+// (X) - CHECK: purchase payment forwarded by share, dust to the last recipient
+// (X) - CHECK: split in purchase attributes
+// (X) - CHECK: shares must sum to 1
+#[test]
+pub fn test_proceeds_split() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use cosmwasm_std::Decimal;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    let treasury = Addr::unchecked("treasury");
+    let community = Addr::unchecked("community");
+    let devfund = Addr::unchecked("devfund");
+    // Contract | 50% treasury, 33% community, 17% devfund
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.proceeds_recipients = vec![
+                (treasury.to_string(), Decimal::percent(50)),
+                (community.to_string(), Decimal::percent(33)),
+                (devfund.to_string(), Decimal::percent(17)),
+            ];
+        });
+
+    let usdc_of = |router: &App, addr: &Addr| -> Uint128 {
+        router.wrap().query_balance(addr.to_string(), NATIVE_USDC).unwrap().amount
+    };
+
+    // 10.001 USDC -> 5_000_500 | 3_300_330 | 1_700_170
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_001_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    ensure!(
+        (usdc_of(router, &treasury) == Uint128::from(5_000_500_u128)
+            && usdc_of(router, &community) == Uint128::from(3_300_330_u128)
+            && usdc_of(router, &devfund) == Uint128::from(1_700_170_u128)
+            && usdc_of(router, &cpbond_contract).is_zero()),
+        here("proceeds not split as configured", line!(), column!())
+    );
+
+    let purchase_res = res.unwrap();
+    let wasm_event = purchase_res.events.iter().find(|e| e.ty == "wasm").unwrap();
+    let attr = |key: &str| {
+        wasm_event.attributes.iter().find(|a| a.key == key).map(|a| a.value.clone())
+    };
+    ensure!(
+        (attr("cost_amount") == Some("10001000".to_string())
+            && attr("proceeds_treasury") == Some("5000500".to_string())
+            && attr("proceeds_community") == Some("3300330".to_string())
+            && attr("proceeds_devfund") == Some("1700170".to_string())),
+        here(format!("purchase attributes: {:#?}", wasm_event.attributes), line!(), column!())
+    );
+
+    // Shares summing to 0.9 are rejected
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig {
+            price_guards: None,
+            limits: None,
+            discount_curve: None,
+            proceeds_recipients: Some(vec![
+                (treasury.to_string(), Decimal::percent(50)),
+                (community.to_string(), Decimal::percent(40)),
+            ]),
+        },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::ProceedsSharesNotOne { .. }))
+        ),
+        here(format!("Shares not summing to 1 should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Empty list keeps proceeds in the contract
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdateConfig {
+            price_guards: None,
+            limits: None,
+            discount_curve: None,
+            proceeds_recipients: Some(vec![]),
+        },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("clear recipients {:#?}", res), line!(), column!()));
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john second buy {:#?}", res), line!(), column!()));
+
+    ensure!(
+        (usdc_of(router, &cpbond_contract) == Uint128::from(10_000_000_u128)
+            && usdc_of(router, &treasury) == Uint128::from(5_000_500_u128)),
+        here("proceeds should've stayed in the contract", line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: fee from payment, net payment priced
+// (X) - CHECK: rounding error with a payment fee reports the amount sent
+// (X) - CHECK: fee from sale-token output, net amount vests
+// (X) - CHECK: only admin can UpdatePurchaseFee, bps below 10_000
+#[test]
+pub fn test_purchase_fee() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::state::{FeeSource, PurchaseFee};
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    let collector = Addr::unchecked("collector");
+    // Contract | 2.5% of the payment
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.purchase_fee = Some(PurchaseFee {
+                bps: 250,
+                collector: collector.clone(),
+                deduct_from: FeeSource::Cost,
+            });
+        });
+
+    let attrs_of = |res: &AppResponse| -> Vec<(String, String)> {
+        let wasm_event = res.events.iter().find(|e| e.ty == "wasm").unwrap();
+        wasm_event.attributes.iter().map(|a| (a.key.clone(), a.value.clone())).collect()
+    };
+    let attr = |attrs: &[(String, String)], key: &str| {
+        attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+    };
+
+    // 10 USDC -> 0.25 USDC fee, 9.75 USDC / 5.001 = 1_949_610 * 1.1 = 2_144_571
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let attrs = attrs_of(&res.unwrap());
+    ensure!(
+        (attr(&attrs, "fee_asset") == Some(NATIVE_USDC.to_string())
+            && attr(&attrs, "gross_amount") == Some("10000000".to_string())
+            && attr(&attrs, "fee_amount") == Some("250000".to_string())
+            && attr(&attrs, "net_amount") == Some("9750000".to_string())
+            && attr(&attrs, "amount_purchased") == Some("2144571".to_string())),
+        here(format!("cost fee attributes: {:#?}", attrs), line!(), column!())
+    );
+
+    let collector_usdc: Coin = router.wrap().query_balance(collector.to_string(), NATIVE_USDC).unwrap();
+    ensure!(
+        (collector_usdc.amount == Uint128::from(250_000_u128)),
+        here(format!("collector usdc: {}", collector_usdc.amount), line!(), column!())
+    );
+
+    // Rounding error reports what john sent, not the amount after the fee
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_500, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NotRoundedToThirdDecimal { got })) if *got == Uint128::from(10_000_500_u128)
+        ),
+        here(format!("Not rounded should've failed with the gross amount: {:#?}", res), line!(), column!())
+    );
+
+    // Non admin
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdatePurchaseFee { purchase_fee: None },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin update fee should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // 100% fee
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdatePurchaseFee {
+            purchase_fee: Some(PurchaseFee {
+                bps: 10_000,
+                collector: collector.clone(),
+                deduct_from: FeeSource::Output,
+            }),
+        },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidFeeBps { .. }))
+        ),
+        here(format!("10_000 bps should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // 1% of the purchased JUNO
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdatePurchaseFee {
+            purchase_fee: Some(PurchaseFee {
+                bps: 100,
+                collector: collector.clone(),
+                deduct_from: FeeSource::Output,
+            }),
+        },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("update fee {:#?}", res), line!(), column!()));
+
+    // 10 USDC -> 2_199_560 JUNO, 21_995 fee, 2_177_565 vests
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john second buy {:#?}", res), line!(), column!()));
+
+    let attrs = attrs_of(&res.unwrap());
+    ensure!(
+        (attr(&attrs, "fee_asset") == Some(NATIVE_JUNO.to_string())
+            && attr(&attrs, "gross_amount") == Some("2199560".to_string())
+            && attr(&attrs, "fee_amount") == Some("21995".to_string())
+            && attr(&attrs, "net_amount") == Some("2177565".to_string())
+            && attr(&attrs, "amount_purchased") == Some("2177565".to_string())),
+        here(format!("output fee attributes: {:#?}", attrs), line!(), column!())
+    );
+
+    let collector_juno: Coin = router.wrap().query_balance(collector.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (collector_juno.amount == Uint128::from(21_995_u128)),
+        here(format!("collector juno: {}", collector_juno.amount), line!(), column!())
+    );
+
+    // Removing the fee drops the fee attributes
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::UpdatePurchaseFee { purchase_fee: None },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("remove fee {:#?}", res), line!(), column!()));
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john third buy {:#?}", res), line!(), column!()));
+
+    let attrs = attrs_of(&res.unwrap());
+    ensure!(
+        (attr(&attrs, "fee_amount").is_none()
+            && attr(&attrs, "amount_purchased") == Some("2199560".to_string())),
+        here(format!("no fee attributes: {:#?}", attrs), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: only the proposed address can accept, before expiry
+// (X) - CHECK: cancelled proposals can't be accepted
+// (X) - CHECK: new admin takes over, old admin loses rights
+// (X) - CHECK: renounced contract has no admin
+#[test]
+pub fn test_admin_transfer() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::query::{AdminResponse, PendingAdminResponse};
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    let get_admin = |router: &App| -> Option<String> {
+        let res: AdminResponse = router.wrap()
+            .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetAdmin {})
+            .unwrap();
+        res.admin
+    };
+    let get_pending = |router: &App| -> PendingAdminResponse {
+        router.wrap()
+            .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetPendingAdmin {})
+            .unwrap()
+    };
+
+    // Non admin can't propose
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ProposeAdmin { new_admin: john.address.to_string(), expiry: None },
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin propose should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Propose sam, expires in 1 hour
+    let expiry = router.block_info().time.plus_seconds(3_600);
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ProposeAdmin { new_admin: sam.address.to_string(), expiry: Some(expiry) },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("propose sam {:#?}", res), line!(), column!()));
+
+    let pending = get_pending(router).pending_admin.unwrap();
+    ensure!(
+        (pending.new_admin == sam.address && pending.expiry == Some(expiry)),
+        here(format!("pending admin: {:#?}", pending), line!(), column!())
+    );
+
+    // john isn't the proposed admin
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::AcceptAdmin {},
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("john accept should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Expired
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 1_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(6_000);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        sam.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::AcceptAdmin {},
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::AdminProposalExpired { .. }))
+        ),
+        here(format!("Expired accept should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Cancelled
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::CancelAdminProposal {},
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("cancel {:#?}", res), line!(), column!()));
+    ensure!(get_pending(router).pending_admin.is_none(), here("Proposal not removed", line!(), column!()));
+
+    // Propose again without expiry, sam accepts
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ProposeAdmin { new_admin: sam.address.to_string(), expiry: None },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("propose sam again {:#?}", res), line!(), column!()));
+
+    let res: Result<AppResponse> = router.execute_contract(
+        sam.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::AcceptAdmin {},
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("sam accept {:#?}", res), line!(), column!()));
+
+    ensure!(
+        (get_admin(router) == Some(sam.address.to_string()) && get_pending(router).pending_admin.is_none()),
+        here("sam should be the only admin", line!(), column!())
+    );
+
+    // Old admin lost rights
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::PausePurchasing {},
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Old admin pause should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        sam.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::PausePurchasing {},
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("sam pause {:#?}", res), line!(), column!()));
+
+    // Renounce
+    let res: Result<AppResponse> = router.execute_contract(
+        sam.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::RenounceAdmin {},
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("sam renounce {:#?}", res), line!(), column!()));
+    ensure!(get_admin(router).is_none(), here("Admin should be None", line!(), column!()));
+
+    let res: Result<AppResponse> = router.execute_contract(
+        sam.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ResumePurchasing {},
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Resume after renounce should've failed: {:#?}", res), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: admin grants and revokes every role, granter only pauser and never for itself
+// (X) - CHECK: each role only unlocks its own messages
+// (X) - CHECK: revoked roles lose access
+#[test]
+pub fn test_roles() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::msg::ExecuteMsg;
+    use crate::query::RolesResponse;
+    use crate::state::Role;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    let exec = |router: &mut App, sender: &Addr, msg: &ExecuteMsg, funds: &[Coin]| -> Result<AppResponse> {
+        router.execute_contract(sender.clone(), cpbond_contract.clone(), msg, funds)
+    };
+    let grant = |address: &Addr, role: Role| ExecuteMsg::GrantRole { address: address.to_string(), role };
+
+    // Non admin can't grant
+    let res = exec(router, &john.address, &grant(&john.address, Role::Pauser), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin grant should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // john | pauser, sam | treasurer, max | config-manager
+    for (user, role) in [(&john, Role::Pauser), (&sam, Role::Treasurer), (&max, Role::ConfigManager)] {
+        let res = exec(router, &admin.address, &grant(&user.address, role), &[]);
+        ensure!(res.is_ok(), here(format!("grant {:#?}", res), line!(), column!()));
+    }
+
+    let res = exec(router, &admin.address, &grant(&john.address, Role::Pauser), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::RoleAlreadyGranted { .. }))
+        ),
+        here(format!("Granting a held role should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let roles: RolesResponse = router.wrap().query_wasm_smart(
+        cpbond_contract.clone(),
+        &crate::msg::QueryMsg::GetRoles { address: john.address.to_string() },
+    )?;
+    ensure!(roles.roles == vec![Role::Pauser], here(format!("john roles: {:?}", roles.roles), line!(), column!()));
+
+    // Pauser
+    let res = exec(router, &john.address, &ExecuteMsg::PausePurchasing {}, &[]);
+    ensure!(res.is_ok(), here(format!("john pause {:#?}", res), line!(), column!()));
+    let res = exec(router, &sam.address, &ExecuteMsg::ResumePurchasing {}, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Treasurer resume should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &john.address, &ExecuteMsg::ResumePurchasing {}, &[]);
+    ensure!(res.is_ok(), here(format!("john resume {:#?}", res), line!(), column!()));
+
+    // Treasurer
+    let withdraw = ExecuteMsg::WithdrawInventory { amount: Uint128::from(1_000_000_u128) };
+    let res = exec(router, &john.address, &withdraw, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Pauser withdraw should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &sam.address, &withdraw, &[]);
+    ensure!(res.is_ok(), here(format!("sam withdraw {:#?}", res), line!(), column!()));
+
+    // Config manager
+    let update = ExecuteMsg::UpdatePurchaseFee { purchase_fee: None };
+    let res = exec(router, &sam.address, &update, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Treasurer config update should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &max.address, &update, &[]);
+    ensure!(res.is_ok(), here(format!("max config update {:#?}", res), line!(), column!()));
+
+    // Anyone can add sale funds
+    router.init_modules(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &max.address, coins(2_000_000, NATIVE_JUNO))
+            .unwrap()
+    });
+    let res = exec(router, &max.address, &ExecuteMsg::AddSaleFunds {}, &coins(1_000_000, NATIVE_JUNO));
+    ensure!(res.is_ok(), here(format!("max add funds {:#?}", res), line!(), column!()));
+
+    // Granter
+    let res = exec(router, &max.address, &grant(&sam.address, Role::Pauser), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non granter grant should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &admin.address, &grant(&max.address, Role::Granter), &[]);
+    ensure!(res.is_ok(), here(format!("grant max granter {:#?}", res), line!(), column!()));
+    let res = exec(router, &max.address, &grant(&sam.address, Role::Pauser), &[]);
+    ensure!(res.is_ok(), here(format!("max grant sam pauser {:#?}", res), line!(), column!()));
+    let res = exec(router, &max.address, &grant(&sam.address, Role::Granter), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Granter granting granter should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &max.address, &grant(&sam.address, Role::Treasurer), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Granter granting treasurer should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &max.address, &grant(&max.address, Role::Pauser), &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Granter granting itself should've failed: {:#?}", res), line!(), column!())
+    );
+    let revoke_sam = ExecuteMsg::RevokeRole { address: sam.address.to_string(), role: Role::Pauser };
+    let res = exec(router, &max.address, &revoke_sam, &[]);
+    ensure!(res.is_ok(), here(format!("max revoke sam pauser {:#?}", res), line!(), column!()));
+    let revoke_max = ExecuteMsg::RevokeRole { address: max.address.to_string(), role: Role::Granter };
+    let res = exec(router, &max.address, &revoke_max, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Granter revoking granter should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Revoke
+    let revoke = ExecuteMsg::RevokeRole { address: john.address.to_string(), role: Role::Pauser };
+    let res = exec(router, &sam.address, &revoke, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin revoke should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &admin.address, &revoke, &[]);
+    ensure!(res.is_ok(), here(format!("revoke john {:#?}", res), line!(), column!()));
+    let res = exec(router, &admin.address, &revoke, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::RoleNotGranted { .. }))
+        ),
+        here(format!("Revoking a missing role should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = exec(router, &john.address, &ExecuteMsg::PausePurchasing {}, &[]);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Revoked pauser should've failed: {:#?}", res), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: direct config and admin changes rejected while timelocked
+// (X) - CHECK: queued changes only execute after activation_height
+// (X) - CHECK: cancelled changes can't be executed
+// (X) - CHECK: queued admin change becomes a pending proposal
+// (X) - CHECK: purchase fee and price guards only change through the queue
+#[test]
+pub fn test_timelocked_config_changes() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::msg::ExecuteMsg;
+    use crate::query::{ConfigResponse, PendingAdminResponse, QueuedChangesResponse};
+    use crate::state::{ConfigChange, DiscountCurve, FeeSource, PriceGuards, PurchaseFee, SaleLimits};
+    use cosmwasm_std::Decimal;
+    use crate::error::ContractError;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    // Contract | 100 block timelock
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.timelock_blocks = 100;
+        });
+
+    let exec = |router: &mut App, sender: &Addr, msg: &ExecuteMsg| -> Result<AppResponse> {
+        router.execute_contract(sender.clone(), cpbond_contract.clone(), msg, &[])
+    };
+    let queued = |router: &App| -> QueuedChangesResponse {
+        router.wrap()
+            .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetQueuedChanges { start_after: None, limit: None })
+            .unwrap()
+    };
+    let config = |router: &App| -> ConfigResponse {
+        router.wrap()
+            .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetConfig {})
+            .unwrap()
+    };
+    let skip_blocks = |router: &mut App, blocks: u64| {
+        router.update_block(|current_blockinfo| {
+            current_blockinfo.height += blocks;
+            current_blockinfo.time = current_blockinfo.time.plus_seconds(blocks * 6);
+        });
+    };
+
+    let new_limits = SaleLimits { max_vest: 26, ..config(router).config.limits };
+
+    // Direct changes
+    let res = exec(router, &admin.address, &ExecuteMsg::UpdateConfig {
+        price_guards: None,
+        limits: Some(new_limits.clone()),
+        discount_curve: None,
+        proceeds_recipients: None,
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Timelocked { .. }))
+        ),
+        here(format!("Direct UpdateConfig should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res = exec(router, &admin.address, &ExecuteMsg::ProposeAdmin { new_admin: sam.address.to_string(), expiry: None });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Timelocked { .. }))
+        ),
+        here(format!("Direct ProposeAdmin should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res = exec(router, &admin.address, &ExecuteMsg::RenounceAdmin {});
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Timelocked { .. }))
+        ),
+        here(format!("Direct RenounceAdmin should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let new_fee = PurchaseFee { bps: 100, collector: max.address.clone(), deduct_from: FeeSource::Cost };
+    let res = exec(router, &admin.address, &ExecuteMsg::UpdatePurchaseFee { purchase_fee: Some(new_fee.clone()) });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Timelocked { .. }))
+        ),
+        here(format!("Direct UpdatePurchaseFee should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let new_guards = PriceGuards { max_deviation: Decimal::percent(50), ..config(router).config.price_guards };
+    let res = exec(router, &admin.address, &ExecuteMsg::UpdateConfig {
+        price_guards: Some(new_guards.clone()),
+        limits: None,
+        discount_curve: None,
+        proceeds_recipients: None,
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Timelocked { .. }))
+        ),
+        here(format!("Direct price guards update should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Non config-manager
+    let res = exec(router, &john.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::Limits { limits: new_limits.clone() },
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("Non admin queue should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Invalid change rejected when queued
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::Limits { limits: SaleLimits { min_vest: 30, ..new_limits.clone() } },
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidLimits { .. }))
+        ),
+        here(format!("Invalid queued limits should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Queue limits | id 1
+    let queued_at = router.block_info().height;
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::Limits { limits: new_limits.clone() },
+    });
+    ensure!(res.is_ok(), here(format!("queue limits {:#?}", res), line!(), column!()));
+
+    let pending = queued(router).changes;
+    ensure!(
+        (pending.len() == 1 && pending[0].0 == 1 && pending[0].1.activation_height == queued_at + 100),
+        here(format!("queued changes: {:#?}", pending), line!(), column!())
+    );
+
+    let res = exec(router, &admin.address, &ExecuteMsg::ExecuteQueuedChange { id: 1 });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::QueuedChangeNotReady { .. }))
+        ),
+        here(format!("Early execute should've failed: {:#?}", res), line!(), column!())
+    );
+
+    skip_blocks(router, 100);
+
+    let res = exec(router, &admin.address, &ExecuteMsg::ExecuteQueuedChange { id: 1 });
+    ensure!(res.is_ok(), here(format!("execute limits {:#?}", res), line!(), column!()));
+    ensure!(
+        (config(router).config.limits == new_limits && queued(router).changes.is_empty()),
+        here("Queued limits not applied", line!(), column!())
+    );
+
+    // 5% a week over 26 weeks is a 130% bonus
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::DiscountCurve { discount_curve: DiscountCurve::Linear { slope: Decimal::percent(5) } },
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidDiscountCurve { .. }))
+        ),
+        here(format!("Bonus over 100% should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Queue curve | id 2, then cancel
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::DiscountCurve { discount_curve: DiscountCurve::Linear { slope: Decimal::percent(3) } },
+    });
+    ensure!(res.is_ok(), here(format!("queue curve {:#?}", res), line!(), column!()));
+
+    let res = exec(router, &admin.address, &ExecuteMsg::CancelQueuedChange { id: 2 });
+    ensure!(res.is_ok(), here(format!("cancel curve {:#?}", res), line!(), column!()));
+
+    skip_blocks(router, 100);
+
+    let res = exec(router, &admin.address, &ExecuteMsg::ExecuteQueuedChange { id: 2 });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::QueuedChangeNotFound { .. }))
+        ),
+        here(format!("Cancelled execute should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Queue admin | id 3
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::Admin { new_admin: sam.address.clone() },
+    });
+    ensure!(res.is_ok(), here(format!("queue admin {:#?}", res), line!(), column!()));
+
+    skip_blocks(router, 100);
+
+    let res = exec(router, &admin.address, &ExecuteMsg::ExecuteQueuedChange { id: 3 });
+    ensure!(res.is_ok(), here(format!("execute admin {:#?}", res), line!(), column!()));
+
+    let pending_admin: PendingAdminResponse = router.wrap()
+        .query_wasm_smart(cpbond_contract.clone(), &crate::msg::QueryMsg::GetPendingAdmin {})?;
+    ensure!(
+        (pending_admin.pending_admin.map(|p| p.new_admin) == Some(sam.address.clone())),
+        here("sam should be the pending admin", line!(), column!())
+    );
+
+    // Queue fee | id 4, guards | id 5
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::PriceGuards { price_guards: PriceGuards { max_price_age: 0, ..new_guards.clone() } },
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidPriceGuards { .. }))
+        ),
+        here(format!("Invalid queued guards should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::PurchaseFee { purchase_fee: Some(new_fee.clone()) },
+    });
+    ensure!(res.is_ok(), here(format!("queue fee {:#?}", res), line!(), column!()));
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::PriceGuards { price_guards: new_guards.clone() },
+    });
+    ensure!(res.is_ok(), here(format!("queue guards {:#?}", res), line!(), column!()));
+
+    skip_blocks(router, 100);
+
+    for id in [4, 5] {
+        let res = exec(router, &admin.address, &ExecuteMsg::ExecuteQueuedChange { id });
+        ensure!(res.is_ok(), here(format!("execute {} {:#?}", id, res), line!(), column!()));
+    }
+    let current = config(router).config;
+    ensure!(
+        (current.purchase_fee == Some(new_fee) && current.price_guards == new_guards),
+        here(format!("Queued fee and guards not applied: {:#?}", current), line!(), column!())
+    );
+
+    // Removing the timelock goes through the queue too | id 6
+    let res = exec(router, &admin.address, &ExecuteMsg::QueueConfigChange {
+        change: ConfigChange::TimelockBlocks { timelock_blocks: 0 },
+    });
+    ensure!(res.is_ok(), here(format!("queue timelock {:#?}", res), line!(), column!()));
+
+    skip_blocks(router, 100);
+
+    let res = exec(router, &admin.address, &ExecuteMsg::ExecuteQueuedChange { id: 6 });
+    ensure!(res.is_ok(), here(format!("execute timelock {:#?}", res), line!(), column!()));
+
+    let res = exec(router, &admin.address, &ExecuteMsg::UpdateConfig {
+        price_guards: None,
+        limits: None,
+        discount_curve: Some(DiscountCurve::Linear { slope: Decimal::percent(2) }),
+        proceeds_recipients: None,
+    });
+    ensure!(res.is_ok(), here(format!("direct update after timelock {:#?}", res), line!(), column!()));
+
+    Ok(())
+}
+
+
+// (X) - CHECK: a 0.1.0 store is rebuilt with the 0.1.0 constants and needs a price_source and price_guards
+// (X) - CHECK: live 0.1.0 positions rewritten with vest_start, counted in LIABILITY and still claimable
+// (X) - CHECK: an over-credited 0.1.0 BALANCE is lowered to what the contract holds beyond LIABILITY
+// (X) - CHECK: purchases continue after migrating
+// (X) - CHECK: migrating from another contract fails
+// (X) - CHECK: downgrades fail
+#[test]
+pub fn test_migrate() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::query::{ConfigResponse, SolvencyResponse, UserPurchaseIds, UserPurchaseInfos};
+    use crate::state::{AssetInfo, DiscountCurve, LegacyPurchase, PriceGuards, PriceSource};
+    use crate::error::ContractError;
+    use cosmwasm_std::Decimal;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, _max) = setup_users::setup(&mut router);
+    // Contract | 0.1.0 store, 10 JUNO unsold but BALANCE says 12 (2 USDC deposit credited as JUNO)
+    // Live positions | 10 weeks, 1_000_000 blocks, 2 JUNO each
+    let oracle = mock_oracle::init_oracle(router, &admin.address, mock_oracle::INITIAL_PRICE);
+    let msg = setup_contract::default_instantiate_msg(&oracle);
+    let old_id = router.store_code(setup_legacy::cpbond_v0_1_contract());
+    let new_id = router.store_code(setup_contract::cpbond_contract());
+    let start_height = router.block_info().height;
+    let legacy_purchase = LegacyPurchase {
+        vest_period: 10,
+        vest_expiration: start_height + 1_000_000,
+        amount_purchased: Uint128::from(2_000_000_u128),
+        already_claimed: Uint128::zero(),
+        last_claim: start_height,
+    };
+    let cpbond_contract = router
+        .instantiate_contract(
+            old_id,
+            admin.address.clone(),
+            &setup_legacy::V0_1InstantiateMsg {
+                admin: admin.address.to_string(),
+                beingsold_denom: NATIVE_JUNO.to_string(),
+                cost_denom: NATIVE_USDC.to_string(),
+                balance: Uint128::from(12_000_000_u128),
+                purchases: vec![
+                    (john.address.to_string(), legacy_purchase.clone()),
+                    (sam.address.to_string(), legacy_purchase),
+                ],
+            },
+            &[],
+            "cp_bond",
+            Some(admin.address.to_string()),
+        )
+        .unwrap();
+    // Unsold + unclaimed
+    router.init_modules(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &cpbond_contract, coins(14_000_000, NATIVE_JUNO))
+            .unwrap()
+    });
+
+    let guards = PriceGuards { max_price_age: 3_600, max_deviation: Decimal::percent(10), max_breaches: 3 };
+    let migrate_msg = MigrateMsg {
+        price_source: Some(PriceSource::Oracle { contract: oracle.clone() }),
+        price_guards: Some(guards.clone()),
+        timelock_blocks: None,
+    };
+    let empty_migrate_msg = MigrateMsg { price_source: None, price_guards: None, timelock_blocks: None };
+
+    // Only admin can migrate
+    let res = router.migrate_contract(john.address.clone(), cpbond_contract.clone(), &migrate_msg, new_id);
+    ensure!(res.is_err(), here("Non admin migrate should've failed", line!(), column!()));
+
+    // 0.1.0 had no price source or guards
+    let res = router.migrate_contract(admin.address.clone(), cpbond_contract.clone(), &empty_migrate_msg, new_id);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::MissingMigrateField { .. }))
+        ),
+        here(format!("Migrate without price source should've failed: {:#?}", res), line!(), column!())
+    );
+    let res = router.migrate_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &MigrateMsg { price_guards: None, ..migrate_msg.clone() },
+        new_id,
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::MissingMigrateField { .. }))
+        ),
+        here(format!("Migrate without price guards should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res = router.migrate_contract(admin.address.clone(), cpbond_contract.clone(), &migrate_msg, new_id);
+    ensure!(res.is_ok(), here(format!("migrate {:#?}", res), line!(), column!()));
+
+    let version = cw2::query_contract_info(&router.wrap(), cpbond_contract.to_string())?;
+    ensure!(
+        (version.version == env!("CARGO_PKG_VERSION")),
+        here(format!("version after migrate: {}", version.version), line!(), column!())
+    );
+
+    let config: ConfigResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetConfig {})?;
+    ensure!(
+        (config.config.admin == Some(admin.address.clone())
+            && config.config.beingsold_asset == AssetInfo::Native { denom: NATIVE_JUNO.to_string() }
+            && config.config.cost_asset == AssetInfo::Native { denom: NATIVE_USDC.to_string() }
+            && config.config.price_source == PriceSource::Oracle { contract: oracle.clone() }
+            && config.config.price_guards == guards
+            && config.config.timelock_blocks == 0
+            && config.config.limits.max_vest == 52
+            && config.config.limits.blocks_in_week == 100_000
+            && config.config.discount_curve == DiscountCurve::Linear { slope: Decimal::percent(1) }
+            && config.config.purchase_fee.is_none()
+            && config.config.proceeds_recipients.is_empty()),
+        here(format!("migrated config: {:#?}", config.config), line!(), column!())
+    );
+
+    let infos: UserPurchaseInfos = router.wrap().query_wasm_smart(
+        cpbond_contract.clone(),
+        &QueryMsg::GetUserPurchaseInfos { user_wallet: john.address.to_string(), start_after: None, limit: None },
+    )?;
+    ensure!(
+        (infos.purchase_infos.len() == 1 && infos.purchase_infos[0].1.vest_start == start_height),
+        here(format!("migrated purchase: {:#?}", infos.purchase_infos), line!(), column!())
+    );
+
+    // Open positions are owed, BALANCE is what's left of the holdings
+    let audit: SolvencyResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::AuditSolvency {})?;
+    ensure!(
+        (audit.liability == Uint128::from(4_000_000_u128)
+            && audit.inventory == Uint128::from(10_000_000_u128)
+            && audit.surplus.is_zero()
+            && audit.deficit.is_zero()),
+        here(format!("migrated audit: {:#?}", audit), line!(), column!())
+    );
+
+    // Stats seeded from the open positions
+    let stats: crate::query::StatsResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetStats {})?;
+    ensure!(
+        (stats.active_positions == 2 && stats.unique_buyers == 2 && stats.total_sold == stats.liability),
+        here(format!("migrated stats: {:#?}", stats), line!(), column!())
+    );
+
+    // New purchase priced by the oracle, uuid continues from PURCHASE_COUNT
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy after migrate {:#?}", res), line!(), column!()));
+
+    let ids: UserPurchaseIds = router.wrap().query_wasm_smart(
+        cpbond_contract.clone(),
+        &QueryMsg::GetUserPurchaseIds { user_wallet: john.address.to_string(), start_after: None, limit: None },
+    )?;
+    ensure!(ids.uuids == vec![1, 3], here(format!("john ids: {:?}", ids.uuids), line!(), column!()));
+
+    // 100_000 blocks * 2 per block, same pace as before migrating
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 100_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(600_000);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        sam.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::ClaimAll { limit: None },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("sam claim after migrate {:#?}", res), line!(), column!()));
+
+    let sam_juno: Coin = router.wrap().query_balance(sam.address.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (sam_juno.amount == Uint128::from(200_000_u128)),
+        here(format!("sam juno: {}", sam_juno.amount), line!(), column!())
+    );
+
+    // Migrating again to the same version is a no-op
+    let res = router.migrate_contract(admin.address.clone(), cpbond_contract.clone(), &empty_migrate_msg, new_id);
+    ensure!(res.is_ok(), here(format!("same version migrate {:#?}", res), line!(), column!()));
+
+    // Another contract
+    let cw20_id = router.store_code(setup_cw20::cw20_contract());
+    let cw20 = router.instantiate_contract(
+        cw20_id,
+        admin.address.clone(),
+        &cw20_base::msg::InstantiateMsg {
+            name: "other token".to_string(),
+            symbol: "OTHER".to_string(),
+            decimals: 6,
+            initial_balances: vec![],
+            mint: None,
+            marketing: None,
+        },
+        &[],
+        "other",
+        Some(admin.address.to_string()),
+    )?;
+    let res = router.migrate_contract(admin.address.clone(), cw20, &empty_migrate_msg, new_id);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::WrongContract { .. }))
+        ),
+        here(format!("Wrong contract migrate should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Downgrade
+    let future_id = router.store_code(setup_legacy::cpbond_v9_contract());
+    let future = router
+        .instantiate_contract(future_id, admin.address.clone(), &msg, &[], "cp_bond_v9", Some(admin.address.to_string()))
+        .unwrap();
+    let res = router.migrate_contract(admin.address.clone(), future, &empty_migrate_msg, new_id);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::CannotDowngrade { .. }))
+        ),
+        here(format!("Downgrade should've failed: {:#?}", res), line!(), column!())
+    );
+
+    Ok(())
+}
+
+
+// (X) - CHECK: governance can pause, replace admin, override config and withdraw without the admin key
+// (X) - CHECK: sudo ignores the timelock
+// (X) - CHECK: sudo can set and remove the purchase fee
+#[test]
+pub fn test_sudo() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::error::ContractError;
+    use crate::query::{AdminResponse, ConfigResponse};
+    use crate::state::{AssetInfo, DiscountCurve, FeeSource, PurchaseFee};
+    use cosmwasm_std::Decimal;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, max) = setup_users::setup(&mut router);
+    // Contract | 100 block timelock
+    let (router, cpbond_contract, _oracle) =
+        setup_contract::setup_custom(router, &admin.address, |msg| {
+            msg.timelock_blocks = 100;
+        });
+
+    let fee = PurchaseFee { bps: 100, collector: max.address.clone(), deduct_from: FeeSource::Cost };
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    // Force pause
+    router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::ForcePause {})?;
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(10_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::PurchasingPaused))
+        ),
+        here(format!("Purchase after force pause should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Replace admin
+    router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::ReplaceAdmin { new_admin: sam.address.to_string() })?;
+
+    let res: AdminResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetAdmin {})?;
+    ensure!(
+        (res.admin == Some(sam.address.to_string())),
+        here(format!("admin after sudo: {:?}", res.admin), line!(), column!())
+    );
+
+    // Override config, skips the timelock
+    router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::OverrideConfig {
+        price_source: None,
+        price_guards: None,
+        limits: None,
+        discount_curve: Some(DiscountCurve::Linear { slope: Decimal::permille(15) }),
+        proceeds_recipients: None,
+        purchase_fee: Some(Some(fee.clone())),
+        timelock_blocks: Some(0),
+    })?;
+
+    let res: ConfigResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetConfig {})?;
+    ensure!(
+        (res.config.discount_curve == DiscountCurve::Linear { slope: Decimal::permille(15) }
+            && res.config.purchase_fee == Some(fee.clone())
+            && res.config.timelock_blocks == 0),
+        here(format!("config after sudo: {:#?}", res.config), line!(), column!())
+    );
+
+    // Some(None) removes the fee, None leaves it
+    router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::OverrideConfig {
+        price_source: None,
+        price_guards: None,
+        limits: None,
+        discount_curve: None,
+        proceeds_recipients: None,
+        purchase_fee: Some(None),
+        timelock_blocks: None,
+    })?;
+
+    let res: ConfigResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetConfig {})?;
+    ensure!(res.config.purchase_fee.is_none(), here(format!("fee after sudo: {:#?}", res.config), line!(), column!()));
+
+    // Invalid overrides are still rejected
+    let res = router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::OverrideConfig {
+        price_source: None,
+        price_guards: None,
+        limits: None,
+        discount_curve: None,
+        proceeds_recipients: Some(vec![(sam.address.to_string(), Decimal::percent(50))]),
+        purchase_fee: None,
+        timelock_blocks: None,
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::ProceedsSharesNotOne { .. }))
+        ),
+        here(format!("Invalid sudo override should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res = router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::OverrideConfig {
+        price_source: None,
+        price_guards: None,
+        limits: None,
+        discount_curve: None,
+        proceeds_recipients: None,
+        purchase_fee: Some(Some(PurchaseFee { bps: 10_000, ..fee })),
+        timelock_blocks: None,
+    });
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InvalidFeeBps { .. }))
+        ),
+        here(format!("Invalid sudo fee should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Emergency withdraw of the purchase proceeds
+    router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::EmergencyWithdraw {
+        asset: AssetInfo::Native { denom: NATIVE_USDC.to_string() },
+        amount: Uint128::from(10_000_000_u128),
+        recipient: sam.address.to_string(),
+    })?;
+
+    let sam_usdc: Coin = router.wrap().query_balance(sam.address.to_string(), NATIVE_USDC).unwrap();
+    ensure!(
+        (sam_usdc.amount == Uint128::from(110_000_000_u128)),
+        here(format!("sam usdc: {}", sam_usdc.amount), line!(), column!())
+    );
+
+    // Emergency withdraw of all unsold JUNO | 1_000_000_000 - 2_199_560
+    router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::EmergencyWithdraw {
+        asset: AssetInfo::Native { denom: NATIVE_JUNO.to_string() },
+        amount: Uint128::from(997_800_440_u128),
+        recipient: sam.address.to_string(),
+    })?;
+
+    let contract_juno: Coin = router.wrap().query_balance(cpbond_contract.to_string(), NATIVE_JUNO).unwrap();
+    ensure!(
+        (contract_juno.amount == Uint128::from(2_199_560_u128)),
+        here(format!("contract juno: {}", contract_juno.amount), line!(), column!())
+    );
+
+    Ok(())
+}
+
+// (X) - CHECK: list queries page with start_after / limit and GetAllPurchases crawls every wallet
+#[test]
+pub fn test_paginated_queries() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    let buy_msg = crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 };
+
+    // John makes 5 purchases (uuids 1..=5), Sam makes 2 (uuids 6, 7)
+    for (user, count) in [(&john, 5), (&sam, 2)] {
+        for x in 0..count {
+            let res: Result<AppResponse> = router.execute_contract(
+                user.address.clone(),
+                cpbond_contract.clone(),
+                &buy_msg,
+                &coins(1_000_000, NATIVE_USDC),
+            );
+            ensure!(res.is_ok(), here(format!("buy {}: {:#?}", x, res), line!(), column!()));
+        }
+    }
+
+    // John's ids in pages of 2
+    let mut ids: Vec<u64> = vec![];
+    let mut start_after: Option<u64> = None;
+    loop {
+        let q = crate::msg::QueryMsg::GetUserPurchaseIds {
+            user_wallet: john.address.to_string(),
+            start_after,
+            limit: Some(2),
+        };
+        let qrez: crate::query::UserPurchaseIds = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q)?;
+        ensure!(qrez.uuids.len() <= 2, here(format!("page over limit: {:#?}", qrez.uuids), line!(), column!()));
+        match qrez.uuids.last() {
+            Some(last) => start_after = Some(*last),
+            None => break,
+        }
+        ids.extend(qrez.uuids);
+    }
+    ensure!(ids == vec![1, 2, 3, 4, 5], here(format!("John's ids: {:#?}", ids), line!(), column!()));
+
+    // Infos page starts after the given uuid
+    let q = crate::msg::QueryMsg::GetUserPurchaseInfos {
+        user_wallet: john.address.to_string(),
+        start_after: Some(3),
+        limit: None,
+    };
+    let qrez: crate::query::UserPurchaseInfos = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q)?;
+    let uuids: Vec<u64> = qrez.purchase_infos.iter().map(|(uuid, _)| *uuid).collect();
+    ensure!(uuids == vec![4, 5], here(format!("John's infos after 3: {:#?}", uuids), line!(), column!()));
+
+    // Every purchase in pages of 3
+    let mut all: Vec<(String, u64)> = vec![];
+    let mut start_after: Option<(String, u64)> = None;
+    loop {
+        let q = crate::msg::QueryMsg::GetAllPurchases { start_after: start_after.clone(), limit: Some(3) };
+        let qrez: crate::query::AllPurchases = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q)?;
+        ensure!(qrez.purchases.len() <= 3, here(format!("page over limit: {:#?}", qrez.purchases), line!(), column!()));
+        match qrez.purchases.last() {
+            Some((wallet, uuid, _)) => start_after = Some((wallet.to_string(), *uuid)),
+            None => break,
+        }
+        all.extend(qrez.purchases.into_iter().map(|(wallet, uuid, _)| (wallet.to_string(), uuid)));
+    }
+    ensure!(all.len() == 7, here(format!("All purchases: {:#?}", all), line!(), column!()));
+    ensure!(
+        all.iter().filter(|(wallet, _)| wallet.as_str() == john.address.as_str()).count() == 5,
+        here(format!("John's purchases: {:#?}", all), line!(), column!())
+    );
+    ensure!(
+        all.iter().filter(|(wallet, uuid)| wallet.as_str() == sam.address.as_str() && *uuid >= 6).count() == 2,
+        here(format!("Sam's purchases: {:#?}", all), line!(), column!())
+    );
+
+    Ok(())
+}
+
+// (X) - CHECK: SimulatePurchase quotes what a purchase would receive, and why it would fail
+#[test]
+pub fn test_simulate_purchase() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    let simulate = |router: &App, cost_amount: u128| -> crate::query::SimulatePurchaseResponse {
+        let q = crate::msg::QueryMsg::SimulatePurchase { cost_amount: Uint128::from(cost_amount), vesting_period: 10_u128 };
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+
+    // Quote for 1 USDC vested 10 weeks
+    let sim = simulate(router, 1_000_000);
+    ensure!(sim.error.is_none(), here(format!("quote should pass: {:#?}", sim), line!(), column!()));
+    let quote = sim.quote.unwrap();
+    ensure!(quote.price == Uint128::from(5_000_000_u128), here(format!("price: {:#?}", quote), line!(), column!()));
+    ensure!(quote.cost_fee.is_zero() && quote.output_fee.is_zero(), here(format!("fees: {:#?}", quote), line!(), column!()));
+    ensure!(quote.gross_amount == quote.amount_purchased, here(format!("amounts: {:#?}", quote), line!(), column!()));
+
+    // Simulating doesn't save anything
+    let q = crate::msg::QueryMsg::GetLastPrice {};
+    let qrez: crate::query::LastPriceResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q)?;
+    ensure!(qrez.last_price.is_none(), here(format!("last price: {:#?}", qrez), line!(), column!()));
+
+    // Purchase matches the quote
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy: {:#?}", res), line!(), column!()));
+
+    let q = crate::msg::QueryMsg::GetUserPurchaseInfos { user_wallet: john.address.to_string(), start_after: None, limit: None };
+    let qrez: crate::query::UserPurchaseInfos = router.wrap().query_wasm_smart(cpbond_contract.clone(), &q)?;
+    let (_, purchase) = &qrez.purchase_infos[0];
+    ensure!(
+        purchase.amount_purchased == quote.amount_purchased && purchase.vest_expiration == quote.vest_expiration,
+        here(format!("purchase: {:#?} | quote: {:#?}", purchase, quote), line!(), column!())
+    );
+
+    // Failed checks come back as the error
+    let sim = simulate(router, 1_000_001);
+    ensure!(
+        sim.quote.is_none() && sim.error.as_deref().is_some_and(|e| e.contains("rounded")),
+        here(format!("unrounded amount: {:#?}", sim), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::PausePurchasing {},
+        &[],
+    );
+    ensure!(res.is_ok(), here("pause", line!(), column!()));
+
+    let sim = simulate(router, 1_000_000);
+    ensure!(
+        sim.error.as_deref() == Some("Purchasing is paused"),
+        here(format!("paused: {:#?}", sim), line!(), column!())
+    );
+
+    Ok(())
+}
+
+// (X) - CHECK: GetClaimable / GetTotalClaimable match what a claim pays out at the current block
+#[test]
+pub fn test_claimable_queries() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    // John buys 2 positions, 10 and 20 weeks, large enough to vest at least 1 per block
+    for vesting_period in [10_u128, 20_u128] {
+        let res: Result<AppResponse> = router.execute_contract(
+            john.address.clone(),
+            cpbond_contract.clone(),
+            &crate::msg::ExecuteMsg::Purchase { vesting_period },
+            &coins(20_000_000, NATIVE_USDC),
+        );
+        ensure!(res.is_ok(), here(format!("john buy: {:#?}", res), line!(), column!()));
+    }
+
+    let claimable = |router: &App, uuid: u64| -> Result<crate::query::ClaimableResponse, cosmwasm_std::StdError> {
+        let q = crate::msg::QueryMsg::GetClaimable { user: john.address.to_string(), uuid };
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q)
+    };
+    let total_claimable = |router: &App| -> crate::query::TotalClaimableResponse {
+        let q = crate::msg::QueryMsg::GetTotalClaimable { user: john.address.to_string() };
+        router.wrap().query_wasm_smart(cpbond_contract.clone(), &q).unwrap()
+    };
+
+    // Nothing vested in the purchase block
+    let info = claimable(router, 1)?;
+    ensure!(info.claimable.is_zero() && !info.vest_per_block.is_zero(), here(format!("{:#?}", info), line!(), column!()));
+
+    // Halfway through the 10 week position
+    router.update_block(|block| {
+        block.height += 500_000;
+        block.time = block.time.plus_seconds(3_000_000);
+    });
+
+    let info = claimable(router, 1)?;
+    ensure!(
+        info.claimable == info.vest_per_block * Uint128::from(500_000_u128),
+        here(format!("half vested: {:#?}", info), line!(), column!())
+    );
+
+    let total = total_claimable(router);
+    let info_2 = claimable(router, 2)?;
+    ensure!(
+        total.claimable == info.claimable + info_2.claimable
+            && total.locked == info.locked + info_2.locked
+            && total.vest_per_block == info.vest_per_block + info_2.vest_per_block
+            && total.vest_expiration == Some(info_2.vest_expiration),
+        here(format!("total: {:#?}", total), line!(), column!())
+    );
+
+    // Claim pays out what the query said
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &crate::msg::ExecuteMsg::ClaimAvailable { uuid: 1_u64 },
+        &[],
+    );
+    let claim_res = res?;
+    let wasm_event = claim_res.events.iter().find(|e| e.ty == "wasm").unwrap();
+    let claimed = wasm_event.attributes.iter().find(|a| a.key == "amount").map(|a| a.value.clone());
+    ensure!(claimed == Some(info.claimable.to_string()), here(format!("claimed: {:#?}", claimed), line!(), column!()));
+
+    let info = claimable(router, 1)?;
+    ensure!(info.claimable.is_zero(), here(format!("after claim: {:#?}", info), line!(), column!()));
+
+    // Fully vested, the rest of the position is claimable
+    router.update_block(|block| {
+        block.height += 1_000_000;
+        block.time = block.time.plus_seconds(6_000_000);
+    });
+
+    let info = claimable(router, 1)?;
+    ensure!(
+        info.locked.is_zero() && info.vest_per_block.is_zero() && !info.claimable.is_zero(),
+        here(format!("fully vested: {:#?}", info), line!(), column!())
+    );
+
+    // Unknown purchase errors
+    ensure!(claimable(router, 99).is_err(), here("unknown uuid", line!(), column!()));
+
+    Ok(())
+}
+
+// (X) - CHECK: GetStats running totals follow deposits, purchases and claims
+#[test]
+pub fn test_stats() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::query::StatsResponse;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    // contract has 1_000_000_000 JUNO (1,000 JUNO)
+    let stats: StatsResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetStats {})?;
+    ensure!(
+        (stats.total_deposited == Uint128::from(1_000_000_000_u128)
+            && stats.inventory == stats.total_deposited
+            && stats.next_uuid == 1
+            && stats.proceeds_raised.is_empty()),
+        here(format!("initial stats: {:#?}", stats), line!(), column!())
+    );
+
+    // John buys twice, Sam once
+    for user in [&john, &john, &sam] {
+        let res: Result<AppResponse> = router.execute_contract(
+            user.address.clone(),
+            cpbond_contract.clone(),
+            &ExecuteMsg::Purchase { vesting_period: 10_u128 },
+            &coins(1_000_000, NATIVE_USDC),
+        );
+        ensure!(res.is_ok(), here(format!("buy {:#?}", res), line!(), column!()));
+    }
+
+    let stats: StatsResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetStats {})?;
+    ensure!(
+        (stats.active_positions == 3
+            && stats.unique_buyers == 2
+            && stats.next_uuid == 4
+            && stats.total_sold == stats.liability
+            && stats.inventory + stats.total_sold == stats.total_deposited
+            && stats.proceeds_raised == vec![(NATIVE_USDC.to_string(), Uint128::from(3_000_000_u128))]),
+        here(format!("stats after buys: {:#?}", stats), line!(), column!())
+    );
+
+    // Fully vested, John claims both
+    router.update_block(|current_blockinfo| {
+        current_blockinfo.height += 1_000_000;
+        current_blockinfo.time = current_blockinfo.time.plus_seconds(6_000_000);
+    });
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::ClaimAll { limit: None },
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("john claim {:#?}", res), line!(), column!()));
+
+    let stats: StatsResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::GetStats {})?;
+    ensure!(
+        (stats.active_positions == 1
+            && stats.unique_buyers == 2
+            && !stats.total_claimed.is_zero()
+            && stats.total_sold - stats.total_claimed == stats.liability),
+        here(format!("stats after claim: {:#?}", stats), line!(), column!())
+    );
+
+    Ok(())
+}
+
+// (X) - CHECK: AuditSolvency sees tokens sent outside AddSaleFunds, SyncInventory credits them, a deficit blocks purchases
+// (X) - CHECK: beingsold_asset and cost_asset can't be the same
+#[test]
+pub fn test_solvency() -> Result<(), anyhow::Error> {
+    use anyhow::Result;
+    use cw_multi_test::AppResponse;
+    use crate::error::ContractError;
+    use crate::msg::SudoMsg;
+    use crate::query::SolvencyResponse;
+    use crate::state::AssetInfo;
+
+    //~~~~~~~~~~~~~~~~~~~~
+    // Setup
+    //~~~~~~~~~~~~~~~~~~~~
+    let mut router = App::default();
+    // Users
+    let (router, admin, john, _sam, _max) = setup_users::setup(&mut router);
+    // Contract
+    let (router, cpbond_contract) = setup_contract::setup(router, &admin.address);
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(res.is_ok(), here(format!("john buy {:#?}", res), line!(), column!()));
+
+    let audit: SolvencyResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::AuditSolvency {})?;
+    ensure!(
+        (audit.holdings == audit.required && audit.surplus.is_zero() && audit.deficit.is_zero() && !audit.liability.is_zero()),
+        here(format!("audit after buy: {:#?}", audit), line!(), column!())
+    );
+
+    // 5 JUNO sent straight to the contract
+    router.init_modules(|router, _, storage| {
+        router.bank.init_balance(storage, &admin.address, coins(5_000_000, NATIVE_JUNO)).unwrap()
+    });
+    router.send_tokens(admin.address.clone(), cpbond_contract.clone(), &coins(5_000_000, NATIVE_JUNO))?;
+
+    let audit: SolvencyResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::AuditSolvency {})?;
+    ensure!(
+        (audit.surplus == Uint128::from(5_000_000_u128)),
+        here(format!("audit after send: {:#?}", audit), line!(), column!())
+    );
+
+    // Only admin can sync, roles don't help
+    router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::GrantRole { address: john.address.to_string(), role: crate::state::Role::Treasurer },
+        &[],
+    )?;
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::SyncInventory {},
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::Unauthorized))
+        ),
+        here(format!("john sync should've failed: {:#?}", res), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::SyncInventory {},
+        &[],
+    );
+    ensure!(res.is_ok(), here(format!("admin sync {:#?}", res), line!(), column!()));
+
+    let synced: SolvencyResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::AuditSolvency {})?;
+    ensure!(
+        (synced.surplus.is_zero() && synced.inventory == audit.inventory + audit.surplus),
+        here(format!("audit after sync: {:#?}", synced), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        admin.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::SyncInventory {},
+        &[],
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::NoSurplus))
+        ),
+        here(format!("second sync should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Emergency withdraw takes tokens owed to john as well
+    router.wasm_sudo(cpbond_contract.clone(), &SudoMsg::EmergencyWithdraw {
+        asset: AssetInfo::Native { denom: NATIVE_JUNO.to_string() },
+        amount: synced.holdings,
+        recipient: admin.address.to_string(),
+    })?;
+    router.execute_contract(admin.address.clone(), cpbond_contract.clone(), &ExecuteMsg::ResumePurchasing {}, &[])?;
+
+    let audit: SolvencyResponse = router.wrap().query_wasm_smart(cpbond_contract.clone(), &QueryMsg::AuditSolvency {})?;
+    ensure!(
+        (!audit.deficit.is_zero()),
+        here(format!("audit after withdraw: {:#?}", audit), line!(), column!())
+    );
+
+    let res: Result<AppResponse> = router.execute_contract(
+        john.address.clone(),
+        cpbond_contract.clone(),
+        &ExecuteMsg::Purchase { vesting_period: 10_u128 },
+        &coins(1_000_000, NATIVE_USDC),
+    );
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::InventoryDeficit { .. }))
+        ),
+        here(format!("buy with deficit should've failed: {:#?}", res), line!(), column!())
+    );
+
+    // Selling the asset buyers pay with can't be told apart from proceeds
+    let oracle = mock_oracle::init_oracle(router, &admin.address, mock_oracle::INITIAL_PRICE);
+    let mut msg = setup_contract::default_instantiate_msg(&oracle);
+    msg.cost_asset = AssetInfo::Native { denom: NATIVE_JUNO.to_string() };
+    let code_id = router.store_code(setup_contract::cpbond_contract());
+    let res = router.instantiate_contract(code_id, admin.address.clone(), &msg, &[], "cp_bond", None);
+    ensure!(
+        matches!(
+            res.as_ref().map_err(|e| e.downcast_ref::<ContractError>()),
+            Err(Some(ContractError::SameAsset { .. }))
+        ),
+        here(format!("Same assets should've failed: {:#?}", res), line!(), column!())
+    );
+
+    Ok(())
+}
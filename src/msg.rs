@@ -1,5 +1,8 @@
-use crate::{state::*, ContractError::*, query::*};
+use crate::{query::*, state::{AssetInfo, ConfigChange, DiscountCurve, PriceGuards, PriceSource, PurchaseFee, Role, SaleLimits}};
 use cosmwasm_schema::{cw_serde, QueryResponses};
+use cosmwasm_std::{Decimal, Timestamp, Uint128};
+use cw20::Cw20ReceiveMsg;
+use serde::{Deserialize, Deserializer};
 //use cosmwasm_std::{to_binary, Binary, Deps, StdResult, Uint128, Order};
 
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -8,8 +11,59 @@ use cosmwasm_schema::{cw_serde, QueryResponses};
 #[cw_serde]
 pub struct InstantiateMsg {
     pub admin: Option<String>,
-    pub beingsold_denom: String,
-    pub cost_denom: String,
+    pub beingsold_asset: AssetInfo,
+    pub cost_asset: AssetInfo,
+    pub price_source: PriceSource,
+    pub price_guards: PriceGuards,
+    pub limits: SaleLimits,
+    pub discount_curve: DiscountCurve,
+    pub proceeds_recipients: Vec<(String, Decimal)>,
+    pub purchase_fee: Option<PurchaseFee>,
+    pub timelock_blocks: u64,
+}
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Migrate
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+#[cw_serde]
+pub struct MigrateMsg {
+    // Only read when migrating from 0.1.0, which had none of these
+    pub price_source: Option<PriceSource>, // required
+    pub price_guards: Option<PriceGuards>, // required
+    pub timelock_blocks: Option<u64>,      // None = no timelock
+}
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Sudo
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Chain governance only, none of these check Config.admin, roles or the timelock
+#[cw_serde]
+pub enum SudoMsg {
+    ForcePause {},
+    // Replaces admin and drops any pending proposal
+    ReplaceAdmin {new_admin: String},
+    OverrideConfig {
+        price_source: Option<PriceSource>,
+        price_guards: Option<PriceGuards>,
+        limits: Option<SaleLimits>,
+        discount_curve: Option<DiscountCurve>,
+        proceeds_recipients: Option<Vec<(String, Decimal)>>,
+        // Some(None) removes the fee
+        #[serde(default, deserialize_with = "double_option")]
+        purchase_fee: Option<Option<PurchaseFee>>,
+        timelock_blocks: Option<u64>,
+    },
+    // Also pauses purchasing, sale asset withdrawals past BALANCE leave positions unbacked
+    EmergencyWithdraw {asset: AssetInfo, amount: Uint128, recipient: String},
+}
+
+// A plain Option<Option<T>> reads null as None, this keeps null as Some(None)
+fn double_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -18,10 +72,46 @@ pub struct InstantiateMsg {
 #[cw_serde]
 pub enum ExecuteMsg {
     PausePurchasing {},
+    ProposeAdmin {new_admin: String, expiry: Option<Timestamp>},
+    AcceptAdmin {},
+    CancelAdminProposal {},
+    RenounceAdmin {},
+    GrantRole {address: String, role: Role},
+    RevokeRole {address: String, role: Role},
     AddSaleFunds {},
+    // Credits beingsold_asset sent without AddSaleFunds into BALANCE
+    SyncInventory {},
+    Receive(Cw20ReceiveMsg),
     ResumePurchasing {},
+    AddPriceSource {contract: String},
+    RemovePriceSource {contract: String},
+    UpdateConfig {
+        price_guards: Option<PriceGuards>,
+        limits: Option<SaleLimits>,
+        discount_curve: Option<DiscountCurve>,
+        proceeds_recipients: Option<Vec<(String, Decimal)>>,
+    },
+    // None removes the fee
+    UpdatePurchaseFee {purchase_fee: Option<PurchaseFee>},
+    // Current price becomes LAST_PRICE without the deviation check
+    ResetPriceAnchor {},
+    QueueConfigChange {change: ConfigChange},
+    ExecuteQueuedChange {id: u64},
+    CancelQueuedChange {id: u64},
+    WithdrawInventory {amount: Uint128},
+    WithdrawProceeds {asset: AssetInfo, amount: Uint128, recipient: String},
     Purchase {vesting_period: u128},
     ClaimAvailable {uuid: u64},
+    ClaimAll {limit: Option<u32>},
+    CheckPrice {},
+    RecordTwapSnapshot {},
+}
+
+// Sent as Cw20ReceiveMsg.msg with a cw20 Send to this contract
+#[cw_serde]
+pub enum ReceiveMsg {
+    AddSaleFunds {},
+    Purchase {vesting_period: u128},
 }
 
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -32,10 +122,68 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     #[returns(AdminResponse)]
     GetAdmin {},
+    #[returns(PendingAdminResponse)]
+    GetPendingAdmin {},
+    #[returns(RolesResponse)]
+    GetRoles {address: String},
     #[returns(ConfigResponse)]
     GetConfig {},
+    #[returns(BonusResponse)]
+    GetBonus {vesting_period: u128},
+    #[returns(DebtRatioResponse)]
+    GetDebtRatio {},
+    #[returns(QueuedChangesResponse)]
+    GetQueuedChanges {start_after: Option<u64>, limit: Option<u32>},
+    #[returns(LastPriceResponse)]
+    GetLastPrice {},
+    #[returns(StatsResponse)]
+    GetStats {},
+    #[returns(SolvencyResponse)]
+    AuditSolvency {},
+    #[returns(SimulatePurchaseResponse)]
+    SimulatePurchase {cost_amount: Uint128, vesting_period: u128},
     #[returns(UserPurchaseIds)]
-    GetUserPurchaseIds {user_wallet: String},
+    GetUserPurchaseIds {user_wallet: String, start_after: Option<u64>, limit: Option<u32>},
     #[returns(UserPurchaseInfos)]
-    GetUserPurchaseInfos { user_wallet: String} 
-}
\ No newline at end of file
+    GetUserPurchaseInfos {user_wallet: String, start_after: Option<u64>, limit: Option<u32>},
+    #[returns(ClaimableResponse)]
+    GetClaimable {user: String, uuid: u64},
+    #[returns(TotalClaimableResponse)]
+    GetTotalClaimable {user: String},
+    #[returns(AllPurchases)]
+    GetAllPurchases {start_after: Option<(String, u64)>, limit: Option<u32>},
+}
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Price Oracle
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Query schema an external price-feed contract must answer
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum OracleQueryMsg {
+    #[returns(OraclePriceResponse)]
+    Price { base_denom: String, quote_denom: String },
+}
+
+#[cw_serde]
+pub struct OraclePriceResponse {
+    pub price: Uint128,         // quote_denom per 1 base_denom, 6 decimals (5_000_000 = 5.0)
+    pub last_updated: Timestamp, // time the feed last updated this price
+}
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// AMM Pair
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Query schema an AMM pair must answer for PriceSource::Twap
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum PairQueryMsg {
+    #[returns(CumulativePriceResponse)]
+    CumulativePrice { base_denom: String, quote_denom: String },
+}
+
+#[cw_serde]
+pub struct CumulativePriceResponse {
+    pub price_cumulative: Uint128, // sum of price (6 decimals) * seconds elapsed, since pair creation
+    pub timestamp: Timestamp,      // time price_cumulative is accurate to
+}
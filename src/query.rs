@@ -1,63 +1,359 @@
 use crate::state::*;
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{StdResult, Binary, Deps, to_binary, Order};
+use crate::error::ContractError;
+use crate::utils::{
+    audit_solvency, calc_bonus, calc_claimable, calc_debt_ratio, calc_vest_per_block, current_bond_debt, inventory_check,
+    quote_purchase, vesting_period_check,
+};
+use cosmwasm_std::{Addr, StdResult, Decimal, Deps, Env, StdError, Order, Uint128};
+use cw_storage_plus::Bound;
 
-pub fn get_admin(deps: Deps) -> StdResult<Binary> {
+// Page size for list queries when no limit is given, and the most a single page can hold
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+pub fn get_admin(deps: Deps) -> StdResult<AdminResponse> {
     let storage = CONFIG.load(deps.storage)?;
-    to_binary(&AdminResponse {
-        admin: storage.admin.into_string(),
+    Ok(AdminResponse {
+        admin: storage.admin.map(|admin| admin.into_string()),
+    })
+}
+
+pub fn get_pending_admin(deps: Deps) -> StdResult<PendingAdminResponse> {
+    Ok(PendingAdminResponse {
+        pending_admin: PENDING_ADMIN.may_load(deps.storage)?,
+    })
+}
+
+pub fn get_roles(deps: Deps, address: String) -> StdResult<RolesResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    Ok(RolesResponse {
+        roles: ROLES.may_load(deps.storage, &address)?.unwrap_or_default(),
+    })
+}
+
+pub fn get_queued_changes(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>
+) -> StdResult<QueuedChangesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let changes = QUEUED_CHANGES
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(QueuedChangesResponse {
+        changes,
     })
 }
 
-pub fn get_config(deps: Deps) -> StdResult<Binary> {
+pub fn get_config(deps: Deps) -> StdResult<ConfigResponse> {
     let config = CONFIG.load(deps.storage)?;
-    to_binary(&ConfigResponse {
+    Ok(ConfigResponse {
         config,
     })
 }
 
-pub fn get_user_purchase_ids(deps: Deps, user_wallet: String) -> StdResult<Binary> {
+pub fn get_bonus(deps: Deps, env: Env, vesting_period: u128) -> StdResult<BonusResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    vesting_period_check(&config.limits, vesting_period)
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+    let debt = current_bond_debt(deps.storage, &config.discount_curve, env.block.height)
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+    let debt_ratio = calc_debt_ratio(debt, BALANCE.load(deps.storage)?);
+    let bonus = calc_bonus(&config.discount_curve, vesting_period, debt_ratio)
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+    Ok(BonusResponse {
+        bonus,
+    })
+}
+
+pub fn get_debt_ratio(deps: Deps, env: Env) -> StdResult<DebtRatioResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let debt = current_bond_debt(deps.storage, &config.discount_curve, env.block.height)
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+    let inventory = BALANCE.load(deps.storage)?;
+    Ok(DebtRatioResponse {
+        debt,
+        inventory,
+        debt_ratio: calc_debt_ratio(debt, inventory),
+    })
+}
 
-    let user = deps.api.addr_validate(&user_wallet)?;
+pub fn get_last_price(deps: Deps) -> StdResult<LastPriceResponse> {
+    let last_price = LAST_PRICE.may_load(deps.storage)?;
+    let consecutive_breaches = PRICE_BREACHES.load(deps.storage)?;
+    Ok(LastPriceResponse {
+        last_price,
+        consecutive_breaches,
+    })
+}
 
-    let user_purchases: Vec<(u64, Purchase)> = PURCHASES
-        .prefix(user)
+pub fn get_stats(deps: Deps) -> StdResult<StatsResponse> {
+    let stats = STATS.load(deps.storage)?;
+    let proceeds_raised = PROCEEDS_RAISED
         .range(deps.storage, None, None, Order::Ascending)
         .collect::<StdResult<Vec<_>>>()?;
+    Ok(StatsResponse {
+        inventory: BALANCE.load(deps.storage)?,
+        total_deposited: stats.total_deposited,
+        total_sold: stats.total_sold,
+        total_claimed: stats.total_claimed,
+        liability: LIABILITY.load(deps.storage)?,
+        proceeds_raised,
+        active_positions: stats.active_positions,
+        unique_buyers: stats.unique_buyers,
+        next_uuid: PURCHASE_COUNT.load(deps.storage)?,
+    })
+}
 
-    let ids = user_purchases
-        .iter()
-        .map(|p| p.0)
-        .collect::<Vec<u64>>();
+pub fn get_audit_solvency(deps: Deps, env: Env) -> StdResult<SolvencyResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    audit_solvency(deps, &config, &env.contract.address)
+        .map_err(|e| StdError::generic_err(e.to_string()))
+}
+
+// Runs the purchase checks and math without saving anything
+// A failed check is returned in error, the quote is kept if only inventory fails
+pub fn get_simulate_purchase(
+    deps: Deps,
+    env: Env,
+    cost_amount: Uint128,
+    vesting_period: u128
+) -> StdResult<SimulatePurchaseResponse> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let quote = match quote_purchase(deps, &env, &config, cost_amount, vesting_period) {
+        Ok(quote) => quote,
+        Err(e) => return Ok(SimulatePurchaseResponse {
+            quote: None,
+            error: Some(e.to_string()),
+        }),
+    };
+
+    Ok(SimulatePurchaseResponse {
+        error: inventory_check(deps.storage, quote.gross_amount).err().map(|e| e.to_string()),
+        quote: Some(quote),
+    })
+}
+
+pub fn get_user_purchase_ids(
+    deps: Deps,
+    user_wallet: String,
+    start_after: Option<u64>,
+    limit: Option<u32>
+) -> StdResult<UserPurchaseIds> {
 
-    to_binary(&UserPurchaseIds {
+    let user = deps.api.addr_validate(&user_wallet)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let ids = PURCHASES
+        .prefix(user)
+        .keys(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<u64>>>()?;
+
+    Ok(UserPurchaseIds {
         uuids: ids
     })
 }
 
-pub fn get_user_purchase_infos(deps: Deps, user_wallet: String) -> StdResult<Binary> {
+pub fn get_user_purchase_infos(
+    deps: Deps,
+    user_wallet: String,
+    start_after: Option<u64>,
+    limit: Option<u32>
+) -> StdResult<UserPurchaseInfos> {
 
     let user = deps.api.addr_validate(&user_wallet)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
 
     let purchase_infos: Vec<(u64, Purchase)> = PURCHASES
         .prefix(user)
-        .range(deps.storage, None, None, Order::Ascending)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
         .collect::<StdResult<Vec<_>>>()?;
 
-    to_binary(&UserPurchaseInfos {
+    Ok(UserPurchaseInfos {
         purchase_infos
     })
 }
 
+pub fn get_claimable(
+    deps: Deps,
+    env: Env,
+    user: String,
+    uuid: u64
+) -> StdResult<ClaimableResponse> {
+
+    let user = deps.api.addr_validate(&user)?;
+    let Some(purchase) = PURCHASES.may_load(deps.storage, (user, uuid))? else {
+        return Err(StdError::generic_err(ContractError::PurchaseNotFound { uuid }.to_string()));
+    };
+
+    claimable_info(uuid, &purchase, env.block.height)
+}
+
+// Sums over every open position, vest_per_block only counts positions still vesting
+pub fn get_total_claimable(
+    deps: Deps,
+    env: Env,
+    user: String
+) -> StdResult<TotalClaimableResponse> {
+
+    let user = deps.api.addr_validate(&user)?;
+
+    let positions = PURCHASES
+        .prefix(user)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.and_then(|(uuid, purchase)| claimable_info(uuid, &purchase, env.block.height)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut total = TotalClaimableResponse {
+        claimable: Uint128::zero(),
+        locked: Uint128::zero(),
+        vest_per_block: Uint128::zero(),
+        vest_expiration: None,
+        positions: vec![],
+    };
+
+    for position in &positions {
+        total.claimable = total.claimable.checked_add(position.claimable)?;
+        total.locked = total.locked.checked_add(position.locked)?;
+        total.vest_per_block = total.vest_per_block.checked_add(position.vest_per_block)?;
+        total.vest_expiration = total.vest_expiration.max(Some(position.vest_expiration));
+    }
+    total.positions = positions;
+
+    Ok(total)
+}
+
+fn claimable_info(
+    uuid: u64,
+    purchase: &Purchase,
+    current_block: u64
+) -> StdResult<ClaimableResponse> {
+
+    let claimable = calc_claimable(purchase, current_block)
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+
+    let locked = purchase.amount_purchased
+        .checked_sub(purchase.already_claimed)?
+        .checked_sub(claimable)?;
+
+    let vest_per_block = if current_block >= purchase.vest_expiration {
+        Uint128::zero()
+    } else {
+        calc_vest_per_block(purchase).map_err(|e| StdError::generic_err(e.to_string()))?
+    };
+
+    Ok(ClaimableResponse {
+        uuid,
+        claimable,
+        locked,
+        vest_per_block,
+        vest_expiration: purchase.vest_expiration,
+    })
+}
+
+// Pages over every wallet, start_after is the last (wallet, uuid) of the previous page
+pub fn get_all_purchases(
+    deps: Deps,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>
+) -> StdResult<AllPurchases> {
+
+    let start = match start_after {
+        Some((wallet, uuid)) => Some(Bound::exclusive((deps.api.addr_validate(&wallet)?, uuid))),
+        None => None,
+    };
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let purchases: Vec<(Addr, u64, Purchase)> = PURCHASES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|((wallet, uuid), purchase)| (wallet, uuid, purchase)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(AllPurchases {
+        purchases
+    })
+}
+
 #[cw_serde]
 pub struct AdminResponse {
-    pub admin: String,
+    pub admin: Option<String>,
+}
+#[cw_serde]
+pub struct PendingAdminResponse {
+    pub pending_admin: Option<PendingAdmin>,
+}
+#[cw_serde]
+pub struct RolesResponse {
+    pub roles: Vec<Role>,
+}
+#[cw_serde]
+pub struct QueuedChangesResponse {
+    pub changes: Vec<(u64, QueuedChange)>,
 }
 #[cw_serde]
 pub struct ConfigResponse {
     pub config: Config,
 }
 #[cw_serde]
+pub struct BonusResponse {
+    pub bonus: Decimal, // 0.1 = purchase receives 10% more
+}
+#[cw_serde]
+pub struct DebtRatioResponse {
+    pub debt: Uint128,      // decayed to current block
+    pub inventory: Uint128, // BALANCE
+    pub debt_ratio: Decimal,
+}
+#[cw_serde]
+pub struct LastPriceResponse {
+    pub last_price: Option<LastPrice>,
+    pub consecutive_breaches: u32,
+}
+#[cw_serde]
+pub struct StatsResponse {
+    pub inventory: Uint128,       // BALANCE, unsold beingsold_asset
+    pub total_deposited: Uint128,
+    pub total_sold: Uint128,
+    pub total_claimed: Uint128,
+    pub liability: Uint128,       // sold but not claimed yet
+    pub proceeds_raised: Vec<(String, Uint128)>, // (cost_asset, amount)
+    pub active_positions: u64,
+    pub unique_buyers: u64,
+    pub next_uuid: u64,
+}
+#[cw_serde]
+pub struct SolvencyResponse {
+    pub holdings: Uint128,  // beingsold_asset held by the contract
+    pub inventory: Uint128, // BALANCE
+    pub liability: Uint128,
+    pub required: Uint128,  // inventory + liability
+    pub surplus: Uint128,   // credited to BALANCE by SyncInventory
+    pub deficit: Uint128,   // purchases are blocked while > 0
+}
+#[cw_serde]
+pub struct PurchaseQuote {
+    pub price: Uint128,          // from price_source, before rounding
+    pub bonus: Decimal,          // 0.1 = purchase receives 10% more
+    pub cost_amount: Uint128,
+    pub cost_fee: Uint128,       // FeeSource::Cost, in cost_asset
+    pub net_cost: Uint128,       // cost_amount - cost_fee, what gets priced
+    pub gross_amount: Uint128,   // beingsold_asset taken from inventory
+    pub output_fee: Uint128,     // FeeSource::Output, in beingsold_asset
+    pub amount_purchased: Uint128, // gross_amount - output_fee, vested to the user
+    pub vest_expiration: u64,
+}
+#[cw_serde]
+pub struct SimulatePurchaseResponse {
+    pub quote: Option<PurchaseQuote>,
+    pub error: Option<String>,   // why the purchase would fail, doesn't include max_positions
+}
+#[cw_serde]
 pub struct UserPurchaseIds {
     pub uuids: Vec<u64>
 }
@@ -66,3 +362,24 @@ pub struct UserPurchaseIds {
 pub struct UserPurchaseInfos {
     pub purchase_infos: Vec<(u64, Purchase)>
 }
+
+#[cw_serde]
+pub struct ClaimableResponse {
+    pub uuid: u64,
+    pub claimable: Uint128,      // ClaimAvailable would send this now
+    pub locked: Uint128,         // not vested yet
+    pub vest_per_block: Uint128, // 0 once fully vested
+    pub vest_expiration: u64,
+}
+#[cw_serde]
+pub struct TotalClaimableResponse {
+    pub claimable: Uint128,
+    pub locked: Uint128,
+    pub vest_per_block: Uint128,
+    pub vest_expiration: Option<u64>, // last position to fully vest, None without positions
+    pub positions: Vec<ClaimableResponse>,
+}
+#[cw_serde]
+pub struct AllPurchases {
+    pub purchases: Vec<(Addr, u64, Purchase)>
+}
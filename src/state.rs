@@ -1,37 +1,251 @@
-use cosmwasm_std::{Addr, Uint128};
+use std::fmt;
+
+use cosmwasm_std::{Addr, Decimal, Empty, Timestamp, Uint128};
 use cw_storage_plus::{Item, Map};
 use cosmwasm_schema::cw_serde;
 
 pub const CONFIG: Item<Config> = Item::new("cp_b_config");
 
+// Admin proposed with ExecuteMsg::ProposeAdmin, waiting for AcceptAdmin
+pub const PENDING_ADMIN: Item<PendingAdmin> = Item::new("pending_admin");
+
+// Roles granted by admin or a Granter, admin implicitly holds every role
+pub const ROLES: Map<&Addr, Vec<Role>> = Map::new("roles");
+
 // If true, ExecuteMsg::Purchase cannot be called
 pub const PAUSED: Item<bool> = Item::new("paused");
 
-// Balance of contract in beingsold_denom
+// Balance of contract in beingsold_asset
 pub const BALANCE: Item<Uint128> = Item::new("balance");
 
+// Amount of beingsold_asset owed to active purchases, not part of BALANCE
+pub const LIABILITY: Item<Uint128> = Item::new("liability");
+
 // Running count of all purchases, used for UUID's, reset at u64::MAX
 pub const PURCHASE_COUNT: Item<u64> = Item::new("purchase_count");
 
+// Last price that passed the price guards, None until the first accepted price
+pub const LAST_PRICE: Item<LastPrice> = Item::new("last_price");
+
+// Consecutive price guard breaches, reset when a price is accepted
+pub const PRICE_BREACHES: Item<u32> = Item::new("price_breaches");
+
+// Cumulative price snapshots of PriceSource::Twap pair, keyed by timestamp in seconds
+pub const TWAP_SNAPSHOTS: Map<u64, Uint128> = Map::new("twap_snapshots");
+
+// Decaying debt used by DiscountCurve::DebtRatio, only tracked while that curve is active
+pub const BOND_DEBT: Item<BondDebt> = Item::new("bond_debt");
+
+// Config changes waiting for Config.timelock_blocks to pass, keyed by id
+pub const QUEUED_CHANGES: Map<u64, QueuedChange> = Map::new("queued_changes");
+
+// Last id handed out to a queued change
+pub const QUEUED_CHANGE_COUNT: Item<u64> = Item::new("queued_change_count");
+
 // Map of all purchases still active
 pub const PURCHASES: Map<(Addr, u64), Purchase> = Map::new("purchases");
 
+// Running totals for QueryMsg::GetStats, kept so the query never scans PURCHASES
+pub const STATS: Item<Stats> = Item::new("stats");
+
+// cost_asset paid in by purchases, fees included, keyed by denom or cw20 address
+pub const PROCEEDS_RAISED: Map<String, Uint128> = Map::new("proceeds_raised");
+
+// Every wallet that has made a purchase, counted once in Stats.unique_buyers
+pub const BUYERS: Map<&Addr, Empty> = Map::new("buyers");
+
 
 #[cw_serde]
 pub struct Config {
-    pub admin: Addr,
-    pub beingsold_denom: String,
-    pub cost_denom: String,
+    pub admin: Option<Addr>, // None once renounced
+    pub beingsold_asset: AssetInfo,
+    pub cost_asset: AssetInfo,
+    pub price_source: PriceSource,
+    pub price_guards: PriceGuards,
+    pub limits: SaleLimits,
+    pub discount_curve: DiscountCurve,
+    // Each purchase's payment is forwarded by share, empty keeps it in the contract
+    pub proceeds_recipients: Vec<(Addr, Decimal)>,
+    pub purchase_fee: Option<PurchaseFee>,
+    // While > 0, ConfigChange fields can only be changed through QueueConfigChange
+    pub timelock_blocks: u64,
+}
+
+// Changes that alter sale terms for buyers, delayed by Config.timelock_blocks
+#[cw_serde]
+pub enum ConfigChange {
+    PriceSource { price_source: PriceSource },
+    DiscountCurve { discount_curve: DiscountCurve },
+    Limits { limits: SaleLimits },
+    ProceedsRecipients { proceeds_recipients: Vec<(Addr, Decimal)> },
+    PurchaseFee { purchase_fee: Option<PurchaseFee> },
+    PriceGuards { price_guards: PriceGuards },
+    // Becomes a PENDING_ADMIN proposal without expiry, still needs AcceptAdmin
+    Admin { new_admin: Addr },
+    TimelockBlocks { timelock_blocks: u64 },
+}
+
+#[cw_serde]
+pub struct QueuedChange {
+    pub change: ConfigChange,
+    pub activation_height: u64,
+}
+
+#[cw_serde]
+pub enum Role {
+    Pauser,        // PausePurchasing, ResumePurchasing
+    Treasurer,     // WithdrawInventory, WithdrawProceeds
+    ConfigManager, // UpdateConfig, UpdatePurchaseFee, Add/RemovePriceSource
+    Granter,       // GrantRole, RevokeRole of Pauser for other addresses
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Pauser => write!(f, "pauser"),
+            Role::Treasurer => write!(f, "treasurer"),
+            Role::ConfigManager => write!(f, "config-manager"),
+            Role::Granter => write!(f, "granter"),
+        }
+    }
+}
+
+#[cw_serde]
+pub struct PendingAdmin {
+    pub new_admin: Addr,
+    pub expiry: Option<Timestamp>, // None = no expiry
+}
+
+// Bonus added on top of the purchased amount for a given vesting period in weeks
+#[cw_serde]
+pub enum DiscountCurve {
+    // bonus = slope * weeks, slope 0.01 = 1% per week
+    Linear { slope: Decimal },
+    // bonus of the highest tier with min_weeks <= weeks, 0 below the first tier
+    Tiered { tiers: Vec<DiscountTier> },
+    // bonus = min(scale * sqrt(weeks), cap)
+    Concave { scale: Decimal, cap: Decimal },
+    // Olympus style, bonus = slope * weeks * max(0, 1 - control_variable * debt / BALANCE)
+    // debt grows with each purchase and decays linearly to 0 over decay_blocks
+    DebtRatio { slope: Decimal, control_variable: Decimal, decay_blocks: u64 },
+}
+
+#[cw_serde]
+pub struct DiscountTier {
+    pub min_weeks: u8,
+    pub bonus: Decimal,
+}
+
+#[cw_serde]
+pub struct SaleLimits {
+    pub min_purchase_amount: Uint128, // in cost_asset
+    pub max_purchase_amount: Uint128, // in cost_asset
+    pub min_vest: u8,                 // in weeks
+    pub max_vest: u8,                 // in weeks
+    pub blocks_in_week: u64,          // changing this only affects new purchases
+    pub max_positions: u32,           // active purchases per wallet
+}
+
+// Cut of each purchase sent to collector
+#[cw_serde]
+pub struct PurchaseFee {
+    pub bps: u16,               // 100 = 1%, must be below 10_000
+    pub collector: Addr,
+    pub deduct_from: FeeSource,
+}
+
+#[cw_serde]
+pub enum FeeSource {
+    Cost,   // in cost_asset, taken from the payment before pricing
+    Output, // in beingsold_asset, taken from the purchased amount after bonus
+}
+
+#[cw_serde]
+pub enum AssetInfo {
+    Native { denom: String },
+    Cw20 { contract: Addr },
+}
+
+// Denom or cw20 contract address, used as the asset id in price queries
+impl fmt::Display for AssetInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetInfo::Native { denom } => write!(f, "{}", denom),
+            AssetInfo::Cw20 { contract } => write!(f, "{}", contract),
+        }
+    }
+}
+
+// Where the price of beingsold_asset (in cost_asset) comes from at purchase time
+#[cw_serde]
+pub enum PriceSource {
+    // Single price-feed contract answering msg::OracleQueryMsg::Price
+    Oracle { contract: Addr },
+    // Median of several price-feed contracts, errors if fewer than min_responses give a valid price
+    Median { sources: Vec<Addr>, min_responses: u32 },
+    // Time-weighted average price of an AMM pair over window seconds, from TWAP_SNAPSHOTS
+    Twap { pair: Addr, window: u64 },
+}
+
+#[cw_serde]
+pub struct PriceGuards {
+    pub max_price_age: u64,     // seconds, oldest price the source may report
+    pub max_deviation: Decimal, // max change from LAST_PRICE, 0.1 = 10%
+    pub max_breaches: u32,      // consecutive breaches before PAUSED is set, 0 = never pause
+}
+
+#[cw_serde]
+pub struct LastPrice {
+    pub price: Uint128,
+    pub timestamp: Timestamp, // block time the price was accepted
+}
+
+#[cw_serde]
+pub struct BondDebt {
+    pub debt: Uint128,   // in beingsold_asset, as of last_decay
+    pub last_decay: u64, // block height debt was last decayed
 }
 
 #[cw_serde]
 pub struct Purchase {
     pub vest_period: u8,           // in weeks, 1 = 1 week, 7 = 7 weeks...
     pub vest_expiration: u64,      // block height when purchase is fully vested
+    pub vest_start: u64,           // block height of purchase, claim pace is fixed from here to vest_expiration
     pub amount_purchased: Uint128, // amount of JUNO originally purchased
     pub already_claimed: Uint128,  // amount of JUNO already claimed
     pub last_claim: u64, // block height of last claim, initially set to block_height when purchased
     //pub closed: bool,    // true if this purchase has been totally claimed
 }
 
+// Amounts in beingsold_asset
+#[cw_serde]
+#[derive(Default)]
+pub struct Stats {
+    pub total_deposited: Uint128, // added with AddSaleFunds
+    pub total_sold: Uint128,      // amount_purchased of every purchase, output fee excluded
+    pub total_claimed: Uint128,
+    pub active_positions: u64,
+    pub unique_buyers: u64,
+}
+
+// Config schema before 0.2.0, only read by migrate
+#[cw_serde]
+pub struct LegacyConfig {
+    pub admin: Addr,
+    pub beingsold_denom: String,
+    pub cost_denom: String,
+}
+
+pub const LEGACY_CONFIG: Item<LegacyConfig> = Item::new("cp_b_config");
+
+// Purchase schema before 0.2.0, only read by migrate
+#[cw_serde]
+pub struct LegacyPurchase {
+    pub vest_period: u8,
+    pub vest_expiration: u64,
+    pub amount_purchased: Uint128,
+    pub already_claimed: Uint128,
+    pub last_claim: u64,
+}
 
+pub const LEGACY_PURCHASES: Map<(Addr, u64), LegacyPurchase> = Map::new("purchases");
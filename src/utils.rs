@@ -1,69 +1,450 @@
 use std::ops::Add;
-use std::ops::AddAssign;
-use std::ops::Sub;
 
 use cosmwasm_std::Order;
+use cw_storage_plus::Bound;
 use cosmwasm_std::Coin;
 use cosmwasm_std::Uint128;
 use cosmwasm_std::{
-    from_binary, to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+    coins, to_json_binary, Addr, Api, BankMsg, CosmosMsg, Decimal, Deps, DepsMut, Env, StdError, StdResult, Storage,
+    Timestamp, WasmMsg,
 };
-use cw2::set_contract_version;
+use cw20::{BalanceResponse as Cw20BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg};
 
-use crate::{BLOCKS_IN_WEEK, MAX_PURCHASE_AMOUNT};
 use crate::error::ContractError;
-use crate::msg::*;
+use crate::msg::{CumulativePriceResponse, OracleQueryMsg, OraclePriceResponse, PairQueryMsg};
+use crate::query::{PurchaseQuote, SolvencyResponse};
 use crate::state::*;
 
+// Most a discount curve may add on top of a purchase, 1 = 100%
+pub const MAX_BONUS: Decimal = Decimal::percent(100);
+
 //~~~~~~~~~~~~~~~~~~~~~~~~~~
 // Checks
 //~~~~~~~~~~~~~~~~~~~~~~~~~~
 
+// Native purchase path, returns the amount of cost_asset sent
 pub fn purchase_funds_check(
-    cost_denom: String, 
+    cost_asset: &AssetInfo, 
     funds: &[Coin]
-) -> Result<(), ContractError> {
+) -> Result<Uint128, ContractError> {
+
+    // cw20 cost assets are paid through Receive
+    let AssetInfo::Native { denom: cost_denom } = cost_asset else {
+        return Err(ContractError::UseCw20Send { asset: cost_asset.to_string() });
+    };
 
     // assert not empty
     if funds.is_empty() {
-        return Err(ContractError::GenericError("Purchase funds empty".to_string()));
+        return Err(ContractError::NoFunds);
     };
 
     // assert only 1 coin
     if funds.len() != 1 {
-        return Err(ContractError::GenericError("More than 1 coin sent".to_string()));
+        return Err(ContractError::MultipleCoins);
     };
 
     // assert denom = cost_denom
-    if funds[0].denom != cost_denom {
-        return Err(ContractError::GenericError("Funds used for purchase wrong denom".to_string()));
+    if &funds[0].denom != cost_denom {
+        return Err(ContractError::WrongDenom {
+            expected: cost_denom.to_string(),
+            got: funds[0].denom.clone(),
+        });
     };
 
+    Ok(funds[0].amount)
+}
+
+
+// Also used by GetBonus so it never quotes a period Purchase rejects
+pub fn vesting_period_check(
+    limits: &SaleLimits,
+    vesting_period: u128
+) -> Result<(), ContractError> {
+
+    if vesting_period > limits.max_vest.into() {
+        return Err(ContractError::VestingPeriodTooLong {
+            max: limits.max_vest,
+            got: vesting_period,
+        });
+    }
+
+    if vesting_period < limits.min_vest.into() {
+        return Err(ContractError::VestingPeriodTooShort {
+            min: limits.min_vest,
+            got: vesting_period,
+        });
+    }
+
+    Ok(())
+}
+
+
+// Shared by native and cw20 purchase paths
+pub fn purchase_amount_check(
+    limits: &SaleLimits,
+    amount: Uint128
+) -> Result<(), ContractError> {
+
     // assert not greater than max purchase amount
-    if funds[0].amount > Uint128::from(MAX_PURCHASE_AMOUNT) {
-        return Err(ContractError::GenericError("Purchase Amount greater than max purchase amount".to_string()));
+    if amount > limits.max_purchase_amount {
+        return Err(ContractError::PurchaseAmountTooHigh {
+            max: limits.max_purchase_amount,
+            got: amount,
+        });
+    }
+
+    // assert not less than min purchase amount
+    if amount < limits.min_purchase_amount {
+        return Err(ContractError::PurchaseAmountTooLow {
+            min: limits.min_purchase_amount,
+            got: amount,
+        });
+    }
+
+    Ok(())
+}
+
+
+// Admin passes every role check
+pub fn role_check(
+    storage: &dyn Storage,
+    config: &Config,
+    sender: &Addr,
+    role: Role
+) -> Result<(), ContractError> {
+
+    if config.admin.as_ref() == Some(sender) {
+        return Ok(());
+    }
+
+    let roles = ROLES.may_load(storage, sender)?.unwrap_or_default();
+
+    if roles.contains(&role) {
+        Ok(())
+    } else {
+        Err(ContractError::Unauthorized)
+    }
+}
+
+
+// Admin and timelock changes need admin, the rest need Role::ConfigManager
+pub fn config_change_role_check(
+    storage: &dyn Storage,
+    config: &Config,
+    sender: &Addr,
+    change: &ConfigChange
+) -> Result<(), ContractError> {
+    match change {
+        ConfigChange::Admin { .. } | ConfigChange::TimelockBlocks { .. } => {
+            if config.admin.as_ref() != Some(sender) {
+                return Err(ContractError::Unauthorized);
+            }
+
+            Ok(())
+        },
+        _ => role_check(storage, config, sender, Role::ConfigManager),
+    }
+}
+
+
+// Admin grants and revokes every role, Role::Granter only Pauser and never for itself
+pub fn grant_role_check(
+    storage: &dyn Storage,
+    config: &Config,
+    sender: &Addr,
+    address: &Addr,
+    role: &Role
+) -> Result<(), ContractError> {
+
+    if config.admin.as_ref() == Some(sender) {
+        return Ok(());
+    }
+
+    if role != &Role::Pauser || address == sender {
+        return Err(ContractError::Unauthorized);
+    }
+
+    role_check(storage, config, sender, Role::Granter)
+}
+
+
+// Direct changes to ConfigChange fields would skip the queue
+pub fn not_timelocked_check(
+    config: &Config
+) -> Result<(), ContractError> {
+
+    if config.timelock_blocks > 0 {
+        return Err(ContractError::Timelocked { blocks: config.timelock_blocks });
     }
 
     Ok(())
 }
 
 
+// Curve and limits are checked against the current config, execute_queued_change checks them again
+// Max bonus depends on both, so either one is checked against the other half of config
+pub fn apply_limits_and_curve(
+    config: &mut Config,
+    limits: Option<SaleLimits>,
+    discount_curve: Option<DiscountCurve>
+) -> Result<(), ContractError> {
+
+    if let Some(limits) = limits {
+        validate_limits(&limits)?;
+        config.limits = limits;
+    }
+
+    if let Some(discount_curve) = discount_curve {
+        config.discount_curve = discount_curve;
+    }
+
+    validate_discount_curve(&config.discount_curve, config.limits.max_vest)
+}
+
+pub fn validate_config_change(
+    api: &dyn Api,
+    config: &Config,
+    change: ConfigChange
+) -> Result<ConfigChange, ContractError> {
+    match change {
+        ConfigChange::PriceSource { price_source } => Ok(ConfigChange::PriceSource {
+            price_source: validate_price_source(api, price_source)?,
+        }),
+        ConfigChange::DiscountCurve { discount_curve } => {
+            apply_limits_and_curve(&mut config.clone(), None, Some(discount_curve.clone()))?;
+            Ok(ConfigChange::DiscountCurve { discount_curve })
+        },
+        ConfigChange::Limits { limits } => {
+            apply_limits_and_curve(&mut config.clone(), Some(limits.clone()), None)?;
+            Ok(ConfigChange::Limits { limits })
+        },
+        ConfigChange::ProceedsRecipients { proceeds_recipients } => Ok(ConfigChange::ProceedsRecipients {
+            proceeds_recipients: validate_proceeds_recipients(
+                api,
+                proceeds_recipients.into_iter().map(|(addr, share)| (addr.into_string(), share)).collect(),
+            )?,
+        }),
+        ConfigChange::PurchaseFee { purchase_fee } => Ok(ConfigChange::PurchaseFee {
+            purchase_fee: validate_purchase_fee(api, purchase_fee)?,
+        }),
+        ConfigChange::PriceGuards { price_guards } => {
+            validate_price_guards(&price_guards)?;
+            Ok(ConfigChange::PriceGuards { price_guards })
+        },
+        ConfigChange::Admin { new_admin } => Ok(ConfigChange::Admin {
+            new_admin: api.addr_validate(new_admin.as_str())?,
+        }),
+        ConfigChange::TimelockBlocks { .. } => Ok(change),
+    }
+}
+
+
 pub fn amount_of_purchases_check(
     wallet: Addr,
+    max_positions: u32,
     deps: Deps
 ) -> Result<(), ContractError> {
 
     let user_purchases: Vec<(u64, Purchase)> = PURCHASES
         .prefix(wallet)
         .range(deps.storage, None, None, Order::Ascending)
-        .collect::<StdResult<Vec<_>>>()
-        .map_err(|_| ContractError::GenericError("Error getting user purchases".to_string()))?;
+        .collect::<StdResult<Vec<_>>>()?;
 
-    if user_purchases.len() >= 5 {
-        return Err(ContractError::GenericError("Cannot have more than 5 purchases".to_string()));
+    if user_purchases.len() >= max_positions as usize {
+        Err(ContractError::TooManyPositions { max: max_positions })
     } else {
-        return Ok(());
+        Ok(())
+    }
+}
+
+
+pub fn validate_asset_info(
+    api: &dyn Api,
+    asset: AssetInfo
+) -> Result<AssetInfo, ContractError> {
+    match asset {
+        AssetInfo::Native { denom } => {
+            if denom.is_empty() {
+                return Err(ContractError::EmptyDenom);
+            }
+
+            Ok(AssetInfo::Native { denom })
+        },
+        AssetInfo::Cw20 { contract } => Ok(AssetInfo::Cw20 {
+            contract: api.addr_validate(contract.as_str())?,
+        }),
+    }
+}
+
+
+// Payments kept in the contract would show up as sale inventory surplus if both were the same
+pub fn validate_sale_assets(
+    api: &dyn Api,
+    beingsold_asset: AssetInfo,
+    cost_asset: AssetInfo
+) -> Result<(AssetInfo, AssetInfo), ContractError> {
+
+    let beingsold_asset = validate_asset_info(api, beingsold_asset)?;
+    let cost_asset = validate_asset_info(api, cost_asset)?;
+
+    if beingsold_asset == cost_asset {
+        return Err(ContractError::SameAsset { asset: beingsold_asset.to_string() });
     }
+
+    Ok((beingsold_asset, cost_asset))
+}
+
+
+pub fn validate_limits(
+    limits: &SaleLimits
+) -> Result<(), ContractError> {
+
+    if limits.max_purchase_amount.is_zero() {
+        return Err(ContractError::InvalidLimits { reason: "max_purchase_amount must be greater than 0".to_string() });
+    }
+
+    if limits.min_purchase_amount > limits.max_purchase_amount {
+        return Err(ContractError::InvalidLimits { reason: "min_purchase_amount greater than max_purchase_amount".to_string() });
+    }
+
+    if limits.min_vest > limits.max_vest {
+        return Err(ContractError::InvalidLimits { reason: "min_vest greater than max_vest".to_string() });
+    }
+
+    if limits.blocks_in_week == 0 {
+        return Err(ContractError::InvalidLimits { reason: "blocks_in_week must be greater than 0".to_string() });
+    }
+
+    if limits.max_positions == 0 {
+        return Err(ContractError::InvalidLimits { reason: "max_positions must be greater than 0".to_string() });
+    }
+
+    Ok(())
+}
+
+
+// Curves never give a smaller bonus for a longer vest, so the bonus at max_vest is the largest one
+pub fn validate_discount_curve(
+    discount_curve: &DiscountCurve,
+    max_vest: u8
+) -> Result<(), ContractError> {
+
+    discount_curve_shape_check(discount_curve)?;
+
+    // Debt ratio 0 gives DebtRatio its full bonus
+    let max_bonus = calc_bonus(discount_curve, max_vest.into(), Decimal::zero())
+        .map_err(|_| ContractError::InvalidDiscountCurve { reason: "bonus at max_vest overflows".to_string() })?;
+
+    if max_bonus > MAX_BONUS {
+        return Err(ContractError::InvalidDiscountCurve {
+            reason: format!("bonus at max_vest is {}, max is {}", max_bonus, MAX_BONUS),
+        });
+    }
+
+    Ok(())
+}
+
+fn discount_curve_shape_check(
+    discount_curve: &DiscountCurve
+) -> Result<(), ContractError> {
+    match discount_curve {
+        DiscountCurve::Linear { .. } => Ok(()),
+        DiscountCurve::Tiered { tiers } => {
+            if tiers.is_empty() {
+                return Err(ContractError::InvalidDiscountCurve { reason: "Tiered curve needs at least 1 tier".to_string() });
+            }
+
+            // assert tiers ordered by min_weeks, and a longer vest never gets a smaller bonus
+            for pair in tiers.windows(2) {
+                if pair[1].min_weeks <= pair[0].min_weeks {
+                    return Err(ContractError::InvalidDiscountCurve { reason: "Tiers must be in ascending min_weeks".to_string() });
+                }
+                if pair[1].bonus < pair[0].bonus {
+                    return Err(ContractError::InvalidDiscountCurve { reason: "Tier bonus can't decrease".to_string() });
+                }
+            }
+
+            Ok(())
+        },
+        DiscountCurve::Concave { cap, .. } => {
+            if cap.is_zero() {
+                return Err(ContractError::InvalidDiscountCurve { reason: "Concave curve cap must be greater than 0".to_string() });
+            }
+
+            Ok(())
+        },
+        DiscountCurve::DebtRatio { control_variable, decay_blocks, .. } => {
+            if control_variable.is_zero() {
+                return Err(ContractError::InvalidDiscountCurve { reason: "control_variable must be greater than 0".to_string() });
+            }
+
+            if *decay_blocks == 0 {
+                return Err(ContractError::InvalidDiscountCurve { reason: "decay_blocks must be greater than 0".to_string() });
+            }
+
+            Ok(())
+        },
+    }
+}
+
+
+// Shares must be non zero and sum to exactly 1, an empty list keeps proceeds in the contract
+pub fn validate_proceeds_recipients(
+    api: &dyn Api,
+    recipients: Vec<(String, Decimal)>
+) -> Result<Vec<(Addr, Decimal)>, ContractError> {
+
+    if recipients.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut validated: Vec<(Addr, Decimal)> = Vec::with_capacity(recipients.len());
+    let mut total = Decimal::zero();
+
+    for (recipient, share) in recipients {
+        let recipient = api.addr_validate(&recipient)?;
+
+        if share.is_zero() {
+            return Err(ContractError::InvalidProceedsRecipients {
+                reason: format!("share of {} is zero", recipient),
+            });
+        }
+
+        if validated.iter().any(|(addr, _)| addr == recipient) {
+            return Err(ContractError::InvalidProceedsRecipients {
+                reason: format!("duplicate recipient {}", recipient),
+            });
+        }
+
+        total = total.checked_add(share).map_err(StdError::from)?;
+        validated.push((recipient, share));
+    }
+
+    if total != Decimal::one() {
+        return Err(ContractError::ProceedsSharesNotOne { total });
+    }
+
+    Ok(validated)
+}
+
+
+pub fn validate_purchase_fee(
+    api: &dyn Api,
+    purchase_fee: Option<PurchaseFee>
+) -> Result<Option<PurchaseFee>, ContractError> {
+
+    let Some(fee) = purchase_fee else {
+        return Ok(None);
+    };
+
+    if fee.bps == 0 || fee.bps >= 10_000 {
+        return Err(ContractError::InvalidFeeBps { bps: fee.bps });
+    }
+
+    Ok(Some(PurchaseFee {
+        collector: api.addr_validate(fee.collector.as_str())?,
+        ..fee
+    }))
 }
 
 
@@ -74,17 +455,470 @@ pub fn claim_vestable_checks(
 
     // if closed, error
     // if purchase.closed {
-    //     return Err(ContractError::GenericError("Cannot claim on a closed purchase".to_string()));
+    //     return Err(ContractError::NothingToClaim);
     // }
 
     // if already_claimed >= amount_purchased, error
     if purchase.already_claimed >= purchase.amount_purchased {
-        return Err(ContractError::GenericError("Already fully vested".to_string()));
+        return Err(ContractError::NothingToClaim);
     }
 
     // if time.now <= last_claim, error
     if current_block <= purchase.last_claim {
-        return Err(ContractError::GenericError("Timing issue".to_string()));
+        return Err(ContractError::NothingToClaim);
+    }
+
+    Ok(())
+}
+
+
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Messages
+//~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+// BankMsg::Send for native assets, Cw20ExecuteMsg::Transfer for cw20 assets
+pub fn asset_transfer_msg(
+    asset: &AssetInfo,
+    recipient: &Addr,
+    amount: Uint128
+) -> Result<CosmosMsg, ContractError> {
+    match asset {
+        AssetInfo::Native { denom } => Ok(CosmosMsg::Bank(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: coins(amount.u128(), denom),
+        })),
+        AssetInfo::Cw20 { contract } => Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract.to_string(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount,
+            })?,
+            funds: vec![],
+        })),
+    }
+}
+
+
+
+pub fn query_asset_balance(
+    deps: Deps,
+    asset: &AssetInfo,
+    address: &Addr
+) -> Result<Uint128, ContractError> {
+    match asset {
+        AssetInfo::Native { denom } => Ok(deps.querier.query_balance(address, denom)?.amount),
+        AssetInfo::Cw20 { contract } => {
+            let res: Cw20BalanceResponse = deps.querier.query_wasm_smart(
+                contract,
+                &Cw20QueryMsg::Balance { address: address.to_string() },
+            )?;
+            Ok(res.balance)
+        },
+    }
+}
+
+
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Price
+//~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+pub fn validate_price_source(
+    api: &dyn Api,
+    price_source: PriceSource
+) -> Result<PriceSource, ContractError> {
+    match price_source {
+        PriceSource::Oracle { contract } => Ok(PriceSource::Oracle {
+            contract: api.addr_validate(contract.as_str())?,
+        }),
+        PriceSource::Median { sources, min_responses } => {
+            let sources = sources
+                .iter()
+                .map(|s| api.addr_validate(s.as_str()))
+                .collect::<StdResult<Vec<Addr>>>()?;
+
+            median_sources_check(&sources, min_responses)?;
+
+            Ok(PriceSource::Median { sources, min_responses })
+        },
+        PriceSource::Twap { pair, window } => {
+            if window == 0 {
+                return Err(ContractError::InvalidPriceSource { reason: "TWAP window must be greater than 0".to_string() });
+            }
+
+            Ok(PriceSource::Twap {
+                pair: api.addr_validate(pair.as_str())?,
+                window,
+            })
+        },
+    }
+}
+
+pub fn median_sources_check(
+    sources: &[Addr],
+    min_responses: u32
+) -> Result<(), ContractError> {
+
+    if min_responses == 0 {
+        return Err(ContractError::InvalidPriceSource { reason: "min_responses must be greater than 0".to_string() });
+    }
+
+    if sources.len() < min_responses as usize {
+        return Err(ContractError::InvalidPriceSource { reason: "Fewer price sources than min_responses".to_string() });
+    }
+
+    // assert no duplicate sources, one feed shouldn't count twice towards the median
+    if sources.iter().enumerate().any(|(i, s)| sources[..i].contains(s)) {
+        return Err(ContractError::InvalidPriceSource { reason: "Duplicate price source".to_string() });
+    }
+
+    Ok(())
+}
+
+pub fn validate_price_guards(
+    price_guards: &PriceGuards
+) -> Result<(), ContractError> {
+
+    if price_guards.max_price_age == 0 {
+        return Err(ContractError::InvalidPriceGuards { reason: "max_price_age must be greater than 0".to_string() });
+    }
+
+    if price_guards.max_deviation.is_zero() {
+        return Err(ContractError::InvalidPriceGuards { reason: "max_deviation must be greater than 0".to_string() });
+    }
+
+    Ok(())
+}
+
+// Price of beingsold_asset in cost_asset, 6 decimals, with the time the source last updated it
+pub fn query_price(
+    deps: Deps,
+    config: &Config,
+    now: Timestamp
+) -> Result<OraclePriceResponse, ContractError> {
+    match &config.price_source {
+        PriceSource::Oracle { contract } => query_oracle(deps, contract, config),
+        PriceSource::Median { sources, min_responses } => {
+            // Sources that error, return zero, or are stale don't count as a response
+            let mut valid: Vec<OraclePriceResponse> = sources
+                .iter()
+                .filter_map(|source| query_oracle(deps, source, config).ok())
+                .filter(|res| {
+                    now.seconds().saturating_sub(res.last_updated.seconds())
+                        <= config.price_guards.max_price_age
+                })
+                .collect();
+
+            let responded = valid.len() as u32;
+            if responded < *min_responses {
+                return Err(ContractError::NotEnoughPriceSources {
+                    responded,
+                    required: *min_responses,
+                });
+            }
+
+            valid.sort_by_key(|v| v.price);
+
+            Ok(OraclePriceResponse {
+                price: calc_median(&valid.iter().map(|v| v.price).collect::<Vec<_>>())?,
+                // Oldest update among the prices used
+                last_updated: valid.iter().map(|v| v.last_updated).min().unwrap_or(now),
+            })
+        },
+        PriceSource::Twap { pair, window } => {
+            let current = query_cumulative_price(deps, pair, config)?;
+            let window_start = current.timestamp.seconds().saturating_sub(*window);
+
+            // Latest snapshot taken at or before the start of the window
+            let snapshot = TWAP_SNAPSHOTS
+                .range(deps.storage, None, Some(Bound::inclusive(window_start)), Order::Descending)
+                .next()
+                .transpose()?;
+
+            let Some((snapshot_time, snapshot_cumulative)) = snapshot else {
+                return Err(ContractError::NotEnoughTwapHistory { window: *window });
+            };
+
+            Ok(OraclePriceResponse {
+                price: calc_twap(
+                    pair,
+                    snapshot_cumulative,
+                    snapshot_time,
+                    current.price_cumulative,
+                    current.timestamp.seconds()
+                )?,
+                last_updated: current.timestamp,
+            })
+        },
+    }
+}
+
+pub fn query_cumulative_price(
+    deps: Deps,
+    pair: &Addr,
+    config: &Config
+) -> Result<CumulativePriceResponse, ContractError> {
+    deps.querier
+        .query_wasm_smart(
+            pair,
+            &PairQueryMsg::CumulativePrice {
+                base_denom: config.beingsold_asset.to_string(),
+                quote_denom: config.cost_asset.to_string(),
+            },
+        )
+        .map_err(|e| ContractError::PriceQueryFailed {
+            contract: pair.to_string(),
+            error: e.to_string(),
+        })
+}
+
+// Saves the pair's current cumulative price when price_source is Twap, otherwise does nothing
+// Snapshots older than the one needed for the current window are pruned
+pub fn record_twap_snapshot(
+    deps: DepsMut,
+    config: &Config
+) -> Result<(), ContractError> {
+
+    let PriceSource::Twap { pair, window } = &config.price_source else {
+        return Ok(());
+    };
+
+    let current = query_cumulative_price(deps.as_ref(), pair, config)?;
+    let storage = deps.storage;
+    let now = current.timestamp.seconds();
+
+    TWAP_SNAPSHOTS.save(storage, now, &current.price_cumulative)?;
+
+    let stale: Vec<u64> = TWAP_SNAPSHOTS
+        .keys(storage, None, Some(Bound::inclusive(now.saturating_sub(*window))), Order::Descending)
+        .skip(1)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    for key in stale {
+        TWAP_SNAPSHOTS.remove(storage, key);
+    }
+
+    Ok(())
+}
+
+pub fn query_oracle(
+    deps: Deps,
+    oracle: &Addr,
+    config: &Config
+) -> Result<OraclePriceResponse, ContractError> {
+
+    let res: OraclePriceResponse = deps
+        .querier
+        .query_wasm_smart(
+            oracle,
+            &OracleQueryMsg::Price {
+                base_denom: config.beingsold_asset.to_string(),
+                quote_denom: config.cost_asset.to_string(),
+            },
+        )
+        .map_err(|e| ContractError::PriceQueryFailed {
+            contract: oracle.to_string(),
+            error: e.to_string(),
+        })?;
+
+    if res.price.is_zero() {
+        return Err(ContractError::ZeroPrice { contract: oracle.to_string() });
+    }
+
+    Ok(res)
+}
+
+
+
+// Drops the price anchor and breach count, used when the price source changes
+pub fn reset_price_history(
+    storage: &mut dyn Storage
+) -> Result<(), ContractError> {
+
+    LAST_PRICE.remove(storage);
+    PRICE_BREACHES.save(storage, &0_u32)?;
+
+    Ok(())
+}
+
+// Errors with StalePrice, StaleAnchor or PriceDeviation if the price breaches a guard
+pub fn price_guard_check(
+    price_guards: &PriceGuards,
+    last_price: Option<&LastPrice>,
+    current: &OraclePriceResponse,
+    now: Timestamp
+) -> Result<(), ContractError> {
+
+    // A price from the future is treated as age 0
+    let age = now.seconds().saturating_sub(current.last_updated.seconds());
+
+    if age > price_guards.max_price_age {
+        return Err(ContractError::StalePrice { age, max_age: price_guards.max_price_age });
+    }
+
+    // No anchor only right after instantiate or a price source change
+    let Some(last) = last_price else {
+        return Ok(());
+    };
+
+    // An old anchor can't bound the deviation, keepers refresh it through CheckPrice
+    let anchor_age = now.seconds().saturating_sub(last.timestamp.seconds());
+
+    if anchor_age > price_guards.max_price_age {
+        return Err(ContractError::StaleAnchor { age: anchor_age, max_age: price_guards.max_price_age });
+    }
+
+    let diff = if current.price > last.price {
+        current.price - last.price
+    } else {
+        last.price - current.price
+    };
+
+    if Decimal::checked_from_ratio(diff, last.price).unwrap_or(Decimal::MAX) > price_guards.max_deviation {
+        return Err(ContractError::PriceDeviation { last: last.price, current: current.price });
+    }
+
+    Ok(())
+}
+
+
+
+// Everything execute_purchase checks and calculates before writing state, SimulatePurchase runs the same code
+// Doesn't check max_positions (needs a wallet) or inventory (see inventory_check)
+pub fn quote_purchase(
+    deps: Deps,
+    env: &Env,
+    config: &Config,
+    cost_amount: Uint128,
+    vesting_period: u128
+) -> Result<PurchaseQuote, ContractError> {
+
+    if PAUSED.load(deps.storage)? {
+        return Err(ContractError::PurchasingPaused);
+    }
+
+    solvency_check(deps, config, &env.contract.address)?;
+
+    let limits = &config.limits;
+
+    vesting_period_check(limits, vesting_period)?;
+
+    // CHECK: within min and max purchase amount
+    purchase_amount_check(limits, cost_amount)?;
+
+    // Checked on what the buyer sent, the cost fee is floored to the 3rd decimal so net_cost stays rounded
+    if cost_amount % Uint128::from(1000_u32) != Uint128::zero() {
+        return Err(ContractError::NotRoundedToThirdDecimal { got: cost_amount });
+    }
+
+    // fully vested block height = vesting_period * blocks_in_week + current_block_height
+    let vesting_blocks = vesting_period
+        .checked_mul(limits.blocks_in_week.into())
+        .ok_or_else(|| ContractError::Overflow { context: "vesting_blocks".to_string() })?;
+
+    let fully_vested_blockheight = vesting_blocks
+        .checked_add(env.block.height.into())
+        .ok_or_else(|| ContractError::Overflow { context: "fully_vested_blockheight".to_string() })?;
+
+    // Get current price from the configured price source, reject if it breaches a guard
+    let current_price = query_price(deps, config, env.block.time)?;
+    let last_price = LAST_PRICE.may_load(deps.storage)?;
+    price_guard_check(&config.price_guards, last_price.as_ref(), &current_price, env.block.time)?;
+
+    // Calculate amount being purchased using vesting_period for discount, price, and amount of funds sent in
+    let contract_balance = BALANCE.load(deps.storage)?;
+    let bond_debt = current_bond_debt(deps.storage, &config.discount_curve, env.block.height)?;
+    let bonus = calc_bonus(
+        &config.discount_curve,
+        vesting_period,
+        calc_debt_ratio(bond_debt, contract_balance)
+    )?;
+
+    // Fee from the payment is rounded down to the 3rd decimal so the net amount can still be priced
+    let cost_fee = match &config.purchase_fee {
+        Some(PurchaseFee { bps, deduct_from: FeeSource::Cost, .. }) => third_dec_floor(calc_fee(*bps, cost_amount)?)?,
+        _ => Uint128::zero(),
+    };
+    let net_cost = cost_amount.checked_sub(cost_fee).map_err(StdError::from)?;
+
+    let gross_amount = calc_juno_amt(bonus, current_price.price, net_cost)?;
+
+    let output_fee = match &config.purchase_fee {
+        Some(PurchaseFee { bps, deduct_from: FeeSource::Output, .. }) => calc_fee(*bps, gross_amount)?,
+        _ => Uint128::zero(),
+    };
+    let amount_purchased = gross_amount.checked_sub(output_fee).map_err(StdError::from)?;
+
+    Ok(PurchaseQuote {
+        price: current_price.price,
+        bonus,
+        cost_amount,
+        cost_fee,
+        net_cost,
+        gross_amount,
+        output_fee,
+        amount_purchased,
+        vest_expiration: fully_vested_blockheight
+            .try_into()
+            .map_err(|_| ContractError::Overflow { context: "vest_expiration".to_string() })?,
+    })
+}
+
+// Compares what the contract holds of beingsold_asset against BALANCE + LIABILITY
+pub fn audit_solvency(
+    deps: Deps,
+    config: &Config,
+    contract: &Addr
+) -> Result<SolvencyResponse, ContractError> {
+
+    let holdings = query_asset_balance(deps, &config.beingsold_asset, contract)?;
+    let inventory = BALANCE.load(deps.storage)?;
+    let liability = LIABILITY.load(deps.storage)?;
+    let required = inventory.checked_add(liability).map_err(StdError::from)?;
+
+    Ok(SolvencyResponse {
+        holdings,
+        inventory,
+        liability,
+        required,
+        surplus: holdings.saturating_sub(required),
+        deficit: required.saturating_sub(holdings),
+    })
+}
+
+// Purchases stop while the contract can't cover what it already owes
+pub fn solvency_check(
+    deps: Deps,
+    config: &Config,
+    contract: &Addr
+) -> Result<(), ContractError> {
+
+    let audit = audit_solvency(deps, config, contract)?;
+
+    if !audit.deficit.is_zero() {
+        return Err(ContractError::InventoryDeficit {
+            holdings: audit.holdings,
+            required: audit.required,
+        });
+    }
+
+    Ok(())
+}
+
+// Make sure contract has enough of the sale asset to complete purchase, output fee included
+pub fn inventory_check(
+    storage: &dyn Storage,
+    gross_amount: Uint128
+) -> Result<(), ContractError> {
+
+    let contract_balance = BALANCE.load(storage)?;
+
+    if gross_amount >= contract_balance {
+        return Err(ContractError::InsufficientInventory {
+            available: contract_balance,
+            requested: gross_amount,
+        });
     }
 
     Ok(())
@@ -97,7 +931,7 @@ pub fn claim_vestable_checks(
 //~~~~~~~~~~~~~~~~~~~~~~~~~~
 pub fn third_dec_ceil(num: Uint128) -> Result<Uint128, ContractError> {
     // add 1000, then divide by 1000, thus ROUNDING UP to the nearest 00_000
-    // if fake price is 12_345_678 <12.345678 USDC per JUNO>
+    // if price is 12_345_678 <12.345678 USDC per JUNO>
     // should be 12_346
 
     // add 1000 <to round up>
@@ -106,30 +940,101 @@ pub fn third_dec_ceil(num: Uint128) -> Result<Uint128, ContractError> {
     // divide by 1000, disposing remainder
     let div_1000 = add_1000
         .checked_div(Uint128::from(1_000_u32))
-        .map_err(|_| ContractError::GenericError("Round third decimal ceil".to_string()))?;
+        .map_err(|_| ContractError::Overflow { context: "Round third decimal ceil".to_string() })?;
 
     // multiply by 1000 so initial size returned
     div_1000
         .checked_mul(Uint128::from(1_000_u32))
-        .map_err(|_| ContractError::GenericError("Round third decimal ceil".to_string()))
+        .map_err(|_| ContractError::Overflow { context: "Round third decimal ceil".to_string() })
 }
 
 pub fn third_dec_floor(num: Uint128) -> Result<Uint128, ContractError> {
     // divide by 1000, disposing remainder
     let div_1000 = num
         .checked_div(Uint128::from(1_000_u32))
-        .map_err(|_| ContractError::GenericError("Round third decimal floor".to_string()))?;
+        .map_err(|_| ContractError::Overflow { context: "Round third decimal floor".to_string() })?;
 
     // multiply by 1000 so initial size returned
     div_1000
         .checked_mul(Uint128::from(1_000_u32))
-        .map_err(|_| ContractError::GenericError("Round third decimal ceil".to_string()))
+        .map_err(|_| ContractError::Overflow { context: "Round third decimal ceil".to_string() })
+}
+
+// Bonus for vesting_period weeks on the configured curve, 0.1 = 10%
+// debt_ratio is only used by DiscountCurve::DebtRatio
+pub fn calc_bonus(
+    discount_curve: &DiscountCurve,
+    vesting_period: u128,
+    debt_ratio: Decimal
+) -> Result<Decimal, ContractError> {
+    match discount_curve {
+        DiscountCurve::Linear { slope } => slope
+            .checked_mul(Decimal::from_atomics(vesting_period, 0).map_err(|_| {
+                ContractError::Overflow { context: "calc_bonus | vesting_period".to_string() }
+            })?)
+            .map_err(|_| ContractError::Overflow { context: "calc_bonus | linear overflow".to_string() }),
+        DiscountCurve::DebtRatio { slope, control_variable, .. } => {
+            let full_bonus = calc_bonus(&DiscountCurve::Linear { slope: *slope }, vesting_period, debt_ratio)?;
+            let scale = Decimal::one().saturating_sub(control_variable.saturating_mul(debt_ratio));
+            Ok(full_bonus * scale)
+        },
+        DiscountCurve::Tiered { tiers } => Ok(tiers
+            .iter()
+            .rev()
+            .find(|tier| u128::from(tier.min_weeks) <= vesting_period)
+            .map(|tier| tier.bonus)
+            .unwrap_or_default()),
+        DiscountCurve::Concave { scale, cap } => {
+            let weeks = Decimal::from_atomics(vesting_period, 0).map_err(|_| {
+                ContractError::Overflow { context: "calc_bonus | vesting_period".to_string() }
+            })?;
+            let bonus = scale
+                .checked_mul(weeks.sqrt())
+                .map_err(|_| ContractError::Overflow { context: "calc_bonus | concave overflow".to_string() })?;
+            Ok(bonus.min(*cap))
+        },
+    }
+}
+
+// Bond debt decayed to current_block, 0 unless discount_curve is DebtRatio
+pub fn current_bond_debt(
+    storage: &dyn Storage,
+    discount_curve: &DiscountCurve,
+    current_block: u64
+) -> Result<Uint128, ContractError> {
+
+    let DiscountCurve::DebtRatio { decay_blocks, .. } = discount_curve else {
+        return Ok(Uint128::zero());
+    };
+
+    let Some(bond_debt) = BOND_DEBT.may_load(storage)? else {
+        return Ok(Uint128::zero());
+    };
+
+    let elapsed = current_block.saturating_sub(bond_debt.last_decay).min(*decay_blocks);
+
+    let decay = bond_debt.debt
+        .checked_multiply_ratio(elapsed, *decay_blocks)
+        .map_err(|_| ContractError::Overflow { context: "current_bond_debt | decay".to_string() })?;
+
+    Ok(bond_debt.debt - decay)
+}
+
+// debt / inventory, an empty inventory with outstanding debt is treated as the max ratio
+pub fn calc_debt_ratio(debt: Uint128, inventory: Uint128) -> Decimal {
+    if debt.is_zero() {
+        Decimal::zero()
+    } else if inventory.is_zero() {
+        Decimal::MAX
+    } else {
+        Decimal::checked_from_ratio(debt, inventory).unwrap_or(Decimal::MAX)
+    }
 }
 
 // Simply adds discount to Juno Amount
 // IE - 10% discount returns Juno Amount * 1.1
 pub fn calc_juno_amt(
-    discount: u128,
+    discount: Decimal,
     base_rate: Uint128,
     user_funds: Uint128,
 ) -> Result<Uint128, ContractError> {
@@ -139,9 +1044,7 @@ pub fn calc_juno_amt(
     // -- So we manually check that funds_sent_in modulo 1000 == 0, and error if not
     // -- AKA - Users should only buy with an amount rounded to third decimal
     if user_funds % Uint128::from(1000_u32) != Uint128::from(0_u32) {
-        return Err(ContractError::GenericError(
-            "Amount sent not rounded to 3rd decimal".to_string(),
-        ));
+        return Err(ContractError::NotRoundedToThirdDecimal { got: user_funds });
     };
 
     // Round price to 3rd decimal ceiling
@@ -153,18 +1056,105 @@ pub fn calc_juno_amt(
     // Juno Amount before Discount bump applied
     let before_discount = Uint128::from(1_000_000_u32)
         .checked_multiply_ratio(floored_funds, rounded_price)
-        .map_err(|_| ContractError::GenericError("calc_juno_amt | before_discount".to_string()))?;
+        .map_err(|_| ContractError::Overflow { context: "calc_juno_amt | before_discount".to_string() })?;
 
     // Juno Amount after Discount bump applied
+    let multiplier = Decimal::one().checked_add(discount).map_err(|_| {
+        ContractError::Overflow { context: "calc_juno_amt | 1 + discount".to_string() }
+    })?;
+
     let after_discount =
-        before_discount.checked_multiply_ratio(100_u128.add(discount), 100_u128).map_err(|_| {
-            ContractError::GenericError("calc_juno_amt | before_discount".to_string())
+        before_discount.checked_mul_floor(multiplier).map_err(|_| {
+            ContractError::Overflow { context: "calc_juno_amt | after_discount".to_string() }
         })?;
 
     Ok(after_discount)
 }
 
 
+// amount * bps / 10_000, rounded down
+pub fn calc_fee(bps: u16, amount: Uint128) -> Result<Uint128, ContractError> {
+    amount
+        .checked_multiply_ratio(bps, 10_000_u128)
+        .map_err(|_| ContractError::Overflow { context: "calc_fee overflow".to_string() })
+}
+
+
+// Each recipient gets amount * share rounded down, the last one also gets the rounding dust
+pub fn calc_proceeds_split(
+    recipients: &[(Addr, Decimal)],
+    amount: Uint128,
+) -> Result<Vec<(Addr, Uint128)>, ContractError> {
+    let mut remaining = amount;
+    let mut split: Vec<(Addr, Uint128)> = Vec::with_capacity(recipients.len());
+
+    for (index, (recipient, share)) in recipients.iter().enumerate() {
+        let part = if index == recipients.len() - 1 {
+            remaining
+        } else {
+            amount.checked_mul_floor(*share).map_err(|_| {
+                ContractError::Overflow { context: "calc_proceeds_split | part".to_string() }
+            })?
+        };
+
+        remaining = remaining.checked_sub(part).map_err(StdError::from)?;
+        split.push((recipient.clone(), part));
+    }
+
+    Ok(split)
+}
+
+
+// Median of sorted, non-empty prices, even lengths average the middle two
+pub fn calc_median(sorted_prices: &[Uint128]) -> Result<Uint128, ContractError> {
+    let len = sorted_prices.len();
+
+    if len == 0 {
+        return Err(ContractError::NotEnoughPriceSources { responded: 0, required: 1 });
+    }
+
+    if len % 2 == 1 {
+        return Ok(sorted_prices[len / 2]);
+    }
+
+    let sum = sorted_prices[len / 2 - 1]
+        .checked_add(sorted_prices[len / 2])
+        .map_err(|_| ContractError::Overflow { context: "calc_median | overflow".to_string() })?;
+
+    Ok(sum / Uint128::from(2_u8))
+}
+
+
+// Average price between two cumulative price readings
+pub fn calc_twap(
+    pair: &Addr,
+    start_cumulative: Uint128,
+    start_time: u64,
+    end_cumulative: Uint128,
+    end_time: u64
+) -> Result<Uint128, ContractError> {
+
+    let elapsed = end_time.saturating_sub(start_time);
+
+    if elapsed == 0 {
+        return Err(ContractError::InvalidTwap { reason: "no time elapsed".to_string() });
+    }
+
+    let price_delta = end_cumulative
+        .checked_sub(start_cumulative)
+        .map_err(|_| ContractError::InvalidTwap { reason: "cumulative price decreased".to_string() })?;
+
+    let twap = price_delta / Uint128::from(elapsed);
+
+    // A flat cumulative would price the sale at the 0.001 rounding floor
+    if twap.is_zero() {
+        return Err(ContractError::ZeroPrice { contract: pair.to_string() });
+    }
+
+    Ok(twap)
+}
+
+
 pub fn calc_amt_vested(
     purchase: &Purchase,
     current_block: u64
@@ -173,44 +1163,63 @@ pub fn calc_amt_vested(
     // amount_purchased - already_claimed = amount_left_to_claim
     let amount_left = purchase.amount_purchased
         .checked_sub(purchase.already_claimed)
-        .map_err(|_| ContractError::GenericError("overflow on amount_purchased - already_claimed error".to_string()))?;
+        .map_err(|_| ContractError::Overflow { context: "overflow on amount_purchased - already_claimed error".to_string() })?;
 
     if current_block >= purchase.vest_expiration {
         // Shouldn't ever be zero 
         if amount_left.is_zero() {
-            return Err(ContractError::GenericError("None left to be vested".to_string()));
+            return Err(ContractError::NothingToClaim);
         } else {
             return Ok((amount_left, true));
         }
     };
 
-    // use vest_period & amount_purchased to determine amount vested_per_block
+    // use vest_start, vest_expiration & amount_purchased to determine amount vested_per_block
     // amount vested per block will be 
-    // vest period in weeks * blocks per week = total_blocks
+    // vest_expiration - vest_start = total_blocks
     // amount_purchased / total_blocks = vest_per_block
-    let total_blocks = Uint128::from(BLOCKS_IN_WEEK)
-        .checked_mul(Uint128::from(purchase.vest_period))
-        .map_err(|_| ContractError::GenericError("Overflow on blocks_in_week * purchase.vest_period".to_string()))?;
-
-    let vest_per_block = purchase.amount_purchased
-        .checked_div(total_blocks)
-        .map_err(|_| ContractError::GenericError("Overflow on amount_purchased / total_blocks".to_string()))?;
+    let vest_per_block = calc_vest_per_block(purchase)?;
 
     // time.now - last_claim = amount of blocks to vest for
     let blocks_to_claim_for = match current_block.saturating_sub(purchase.last_claim) {
-        0 => Err(ContractError::GenericError("Current block <= last claim".to_string())),
+        0 => Err(ContractError::NothingToClaim),
         x => Ok(x)
     }?;
 
     // use vested_per_block & amount_blocks_to_vest_for to calculate JUNO to sent to user
     let claim_amount = Uint128::from(blocks_to_claim_for)
         .checked_mul(vest_per_block)
-        .map_err(|_| ContractError::GenericError("Overflow on vest_per_block * blocks_to_claim_for".to_string()))?;
+        .map_err(|_| ContractError::Overflow { context: "Overflow on vest_per_block * blocks_to_claim_for".to_string() })?;
 
 
     Ok((claim_amount, false))
 
 }
 
+// amount_purchased / (vest_expiration - vest_start)
+pub fn calc_vest_per_block(purchase: &Purchase) -> Result<Uint128, ContractError> {
+    let total_blocks = Uint128::from(purchase.vest_expiration)
+        .checked_sub(Uint128::from(purchase.vest_start))
+        .map_err(|_| ContractError::Overflow { context: "Overflow on vest_expiration - vest_start".to_string() })?;
 
+    purchase.amount_purchased
+        .checked_div(total_blocks)
+        .map_err(|_| ContractError::Overflow { context: "Overflow on amount_purchased / total_blocks".to_string() })
+}
+
+// What ClaimAvailable would pay out at current_block, 0 where it would error with NothingToClaim
+pub fn calc_claimable(
+    purchase: &Purchase,
+    current_block: u64
+) -> Result<Uint128, ContractError> {
+
+    if claim_vestable_checks(purchase, current_block).is_err() {
+        return Ok(Uint128::zero());
+    }
 
+    match calc_amt_vested(purchase, current_block) {
+        Ok((amount, _)) => Ok(amount),
+        Err(ContractError::NothingToClaim) => Ok(Uint128::zero()),
+        Err(e) => Err(e),
+    }
+}